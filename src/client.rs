@@ -1,23 +1,52 @@
-use std::fs::File;
 use std::env;
-use std::io::Read;
+use std::net::IpAddr;
+use std::process;
 
 use dns::buffer::DnsBuffer;
 use dns::packet::DnsPacket;
 use dns::client::DnsClient;
 
+fn usage() -> ! {
+    eprintln!("Usage: dnsclient <name> [type] [class]");
+    eprintln!("       dnsclient -x <address>");
+    process::exit(1);
+}
+
 fn main() {
     let args: Vec<String> = env::args().collect();
-    let mut buf = DnsBuffer::new();
-    let mut query_type = String::from("A");
-    if args.len() > 2 {
-        query_type = String::from(&args[2]);
+    if args.len() < 2 {
+        usage();
     }
+    let mut buf = DnsBuffer::new();
 
-    let packet = DnsPacket::from_query(&args[1], &query_type).unwrap();
+    let client = DnsClient::new().unwrap();
+    let mut packet = if args[1] == "-x" {
+        // Reverse lookup, as in dig -x
+        if args.len() != 3 {
+            usage();
+        }
+        let addr: IpAddr = args[2].parse().unwrap_or_else(|err| {
+            eprintln!("Invalid address {}: {}", args[2], err);
+            process::exit(1);
+        });
+        DnsPacket::from_reverse_query(addr).unwrap()
+    } else {
+        let mut query_type = String::from("A");
+        if args.len() > 2 {
+            query_type = String::from(&args[2]);
+        }
+        let mut query_class = String::from("IN");
+        if args.len() > 3 {
+            query_class = String::from(&args[3]);
+        }
+        DnsPacket::from_query_class(&args[1], &query_type, &query_class).unwrap_or_else(|err| {
+            eprintln!("Invalid query: {}", err);
+            process::exit(1);
+        })
+    };
+    packet.add_edns(client.udp_payload_size);
     packet.write(&mut buf).unwrap();
 
-    let client = DnsClient::new().unwrap();
     let response_packet = client.query(&buf).unwrap();
 
     println!("{}", response_packet);
@@ -1,23 +1,83 @@
-use std::io::{Result, Error, ErrorKind, Read};
-use std::fs::File;
+use std::collections::HashMap;
+use crate::error::{DnsError, Result};
+
+/// The largest message that can be sent over classic UDP, without EDNS.
+pub const UDP_MAX_SIZE: usize = 512;
+/// The UDP payload size advertised through EDNS, small enough to avoid IP fragmentation
+/// on most networks.
+pub const EDNS_UDP_SIZE: usize = 4096;
+/// The largest message that can be sent over TCP, limited by its 2 byte length prefix.
+pub const TCP_MAX_SIZE: usize = 65535;
+
+/// The longest name allowed on the wire, counting length bytes.  See section
+/// 2.3.4 of https://www.ietf.org/rfc/rfc1035.txt
+pub const MAX_NAME_SIZE: usize = 255;
+/// The most compression pointers a single name may follow, enough for a name
+/// of nothing but one byte labels.
+pub const MAX_POINTER_HOPS: usize = MAX_NAME_SIZE / 2;
 
 /// Handles all the reading and writing of DNS packets.
 pub struct DnsBuffer {
     pub pos: usize,
-    pub buf: [u8; 512]
+    // Backing storage, always allocated to the full capacity of the buffer
+    pub buf: Vec<u8>,
+    // Number of bytes actually received or written, reads stop here
+    pub len: usize,
+    // Offsets of every name suffix written so far, used for compression
+    pub labels: HashMap<String, usize>,
+    // Cleared to write every name in full, as needed for the canonical form
+    // that DNSSEC signatures are computed over
+    pub compress: bool,
 }
 
 impl DnsBuffer {
+    /// Creates a buffer sized for a classic 512 byte UDP message.
     pub fn new() -> DnsBuffer {
+        DnsBuffer::with_capacity(UDP_MAX_SIZE)
+    }
+
+    /// Creates a buffer able to hold a message of up to `capacity` bytes, such
+    /// as a negotiated EDNS payload size or `TCP_MAX_SIZE`.
+    pub fn with_capacity(capacity: usize) -> DnsBuffer {
         DnsBuffer {
             pos: 0,
-            buf: [0 as u8; 512]
+            buf: vec![0_u8; capacity],
+            len: 0,
+            labels: HashMap::new(),
+            compress: true,
+        }
+    }
+
+    /// Creates a buffer holding a copy of an already received message.
+    pub fn from_bytes(bytes: &[u8]) -> DnsBuffer {
+        let mut buf = DnsBuffer::with_capacity(bytes.len());
+        buf.buf.copy_from_slice(bytes);
+        buf.len = bytes.len();
+        buf
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Marks the first `len` bytes of the buffer as valid, typically after
+    /// receiving a message straight into `buf`.
+    pub fn set_len(&mut self, len: usize) -> Result<()> {
+        if len > self.capacity() {
+            return Err(DnsError::BufferFull { offset: len });
         }
+        self.len = len;
+        Ok(())
+    }
+
+    /// The bytes received or written so far.
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
     }
 
     pub fn get(&self, pos: usize) -> Result<u8> {
-        if pos >= 512 {
-            return Err(Error::new(ErrorKind::InvalidInput, "Position is beyond the buffer"));
+        if pos >= self.len {
+            return Err(DnsError::Truncated { offset: pos });
         }
         Ok(self.buf[pos])
     }
@@ -37,10 +97,11 @@ impl DnsBuffer {
     }
 
     pub fn set(&mut self, pos: usize, val: u8) -> Result<()> {
-        if pos >= 512 {
-            return Err(Error::new(ErrorKind::InvalidInput, "Set position is beyond the buffer"));
+        if pos >= self.capacity() {
+            return Err(DnsError::BufferFull { offset: pos });
         }
         self.buf[pos] = val;
+        self.len = self.len.max(pos + 1);
         Ok(())
     }
 
@@ -58,27 +119,27 @@ impl DnsBuffer {
         let mut output_str = String::new();
         self.read_label(&mut output_str)?;
         self.pos = tmp_pos;
-        Ok(String::from(output_str))
+        Ok(output_str)
     }
 
     pub fn get_range(&self, pos: usize, len: usize) -> Result<&[u8]> {
-        if pos + len >= 512 {
-            return Err(Error::new(ErrorKind::InvalidInput, "Getting a range beyond the buffer"))
+        if pos + len > self.len {
+            return Err(DnsError::Truncated { offset: pos + len })
         }
         Ok(&self.buf[pos..pos + len])
     }
 
     pub fn seek(&mut self, pos: usize) -> Result<()> {
-        if pos >= 512 {
-            return Err(Error::new(ErrorKind::InvalidInput, "Seeking beyond the buffer"))
+        if pos > self.capacity() {
+            return Err(DnsError::BufferFull { offset: pos })
         }
         self.pos = pos;
         Ok(())
     }
 
     pub fn read(&mut self) -> Result<u8> {
-        if self.pos >= 512 {
-            return Err(Error::new(ErrorKind::InvalidInput, "Reading beyond the buffer"))
+        if self.pos >= self.len {
+            return Err(DnsError::Truncated { offset: self.pos })
         }
         let res = self.buf[self.pos];
         self.pos += 1;
@@ -86,11 +147,19 @@ impl DnsBuffer {
     }
 
     pub fn write(&mut self, byte: u8) -> Result<()> {
-        if self.pos >= 512 {
-            return Err(Error::new(ErrorKind::InvalidInput, "Writing beyond the buffer"))
+        if self.pos >= self.capacity() {
+            return Err(DnsError::BufferFull { offset: self.pos })
         }
         self.buf[self.pos] = byte;
         self.pos += 1;
+        self.len = self.len.max(self.pos);
+        Ok(())
+    }
+
+    pub fn write_bytes(&mut self, bytes: &[u8]) -> Result<()> {
+        for byte in bytes {
+            self.write(*byte)?;
+        }
         Ok(())
     }
 
@@ -128,69 +197,225 @@ impl DnsBuffer {
         Ok(())
     }
 
+    /// Reads a DNS label sequence, such as google.com, following any
+    /// compression pointers.  See `read_name` for the checks made along the way,
+    /// and `escape_label` for how unusual bytes in labels are shown.
     pub fn read_label(&mut self, output_str: &mut String) -> Result<()> {
-        let mut pos = self.pos;
-        let mut is_jump = false;
         let mut delimiter = "";
+        let end = read_name(&self.buf[..self.len], self.pos, |label| {
+            output_str.push_str(delimiter);
+            escape_label(label, output_str);
+            // All delimiters after the first will be a period, as in google.com
+            delimiter = ".";
+        })?;
+        self.seek(end)
+    }
 
-        loop {
-            // Check to see if the next byte is a jump value.  A jump value
-            // will always begin with 2 1-bits, followed by the jump position.
-            let seek = self.get(pos)?;
-            let is_jump_cur = (seek & 0xC0) == 0xC0;
-            if is_jump_cur {
-                if !is_jump {
-                    is_jump = true;
-                    // Move to the jump point, if we are not parsing
-                    // a label at a jump position
-                    self.seek(pos)?;
-                }
-                let next = self.get(pos + 1)?;
-                let jump_value = seek ^ 0xC0;
-                let jump_value = (jump_value as u16) << 8 | (next as u16);
-
-                // Jump to that value.
-                pos = jump_value as usize;
-            } else {
-                // Get the length of the next sequence.  If it is zero, we're finished.
-                let len = self.get(pos)?;
-                pos += 1;
-                if len == 0 {
-                    break;
-                }
-                output_str.push_str(delimiter);
-                output_str.push_str(&String::from_utf8_lossy(self.get_range(pos, len as usize)?));
-                // All delimiters after the first will be a period, as in google.com
-                delimiter = ".";
-                pos += len as usize;
-            }
-        }
+    /// Reads a length-prefixed character-string, as used by TXT records.
+    pub fn read_character_string(&mut self) -> Result<Vec<u8>> {
+        let len = self.read()? as usize;
+        let string = self.get_range(self.pos, len)?.to_vec();
+        self.seek(self.pos + len)?;
+        Ok(string)
+    }
 
-        if is_jump {
-            // Skip past the jump value
-            self.seek(self.pos + 2)?;
-        } else {
-            self.seek(pos)?;
+    pub fn write_character_string(&mut self, string: &[u8]) -> Result<()> {
+        if string.len() > 0xFF {
+            return Err(DnsError::InvalidInput(String::from("Character-string exceeds 255 bytes")));
+        }
+        self.write(string.len() as u8)?;
+        for byte in string {
+            self.write(*byte)?;
         }
-        
         Ok(())
     }
 
+    /// Writes a DNS label sequence, replacing any suffix that has already been
+    /// written to the buffer with a compression pointer to it.  See section 4.1.4
+    /// of https://www.ietf.org/rfc/rfc1035.txt for details.
     pub fn write_label(&mut self, label: &str) -> Result<()> {
-        for part in label.split('.') {
-            let len = part.len();
-            self.write(len as u8)?;
-            for byte in part.as_bytes() {
-                self.write(*byte)?;
+        if !self.compress {
+            return self.write_uncompressed_label(label);
+        }
+        let parts = split_name(label);
+
+        for idx in 0..parts.len() {
+            let suffix = parts[idx..].join(".");
+            if let Some(&jump_pos) = self.labels.get(&suffix) {
+                self.write_u16(0xC000 | jump_pos as u16)?;
+                return Ok(());
+            }
+
+            // Pointers only have 14 bits to work with, so anything written past
+            // that can't be jumped to later.
+            if self.pos <= 0x3FFF {
+                self.labels.insert(suffix, self.pos);
             }
+            self.write_label_part(parts[idx])?;
+        }
+        self.write(0)?;
+        Ok(())
+    }
+
+    /// Writes a DNS label sequence in full, without compression.  Needed for
+    /// record types whose RDATA must not contain pointers.
+    pub fn write_uncompressed_label(&mut self, label: &str) -> Result<()> {
+        for part in split_name(label) {
+            self.write_label_part(part)?;
         }
         self.write(0)?;
         Ok(())
     }
+
+    fn write_label_part(&mut self, part: &str) -> Result<()> {
+        let part = unescape_label(part)?;
+        if part.len() > 0x3F {
+            return Err(DnsError::LabelTooLong { offset: self.pos });
+        }
+        self.write(part.len() as u8)?;
+        self.write_bytes(&part)
+    }
+}
+
+/// Splits a name in master file format into its labels, on every dot that
+/// isn't escaped with a backslash.  The labels keep their escapes, and empty
+/// ones, such as after a trailing dot, are left out.
+pub fn split_name(name: &str) -> Vec<&str> {
+    let mut labels = Vec::new();
+    let mut start = 0;
+    let mut escaped = false;
+    for (idx, byte) in name.bytes().enumerate() {
+        if escaped {
+            escaped = false;
+        } else if byte == b'\\' {
+            escaped = true;
+        } else if byte == b'.' {
+            if idx > start {
+                labels.push(&name[start..idx]);
+            }
+            start = idx + 1;
+        }
+    }
+    if name.len() > start {
+        labels.push(&name[start..]);
+    }
+    labels
+}
+
+/// Appends a label in master file format, as described in section 5.1 of
+/// https://www.ietf.org/rfc/rfc1035.txt.  Dots, backslashes and the other
+/// characters special to master files are escaped as `\.`, and bytes that
+/// aren't printable ASCII, spaces included, as `\DDD` in decimal.
+pub fn escape_label(label: &[u8], out: &mut String) {
+    for &byte in label {
+        match byte {
+            b'.' | b'\\' | b'"' | b'(' | b')' | b';' | b'@' | b'$' => {
+                out.push('\\');
+                out.push(byte as char);
+            },
+            0x21..=0x7E => out.push(byte as char),
+            _ => out.push_str(&format!("\\{:03}", byte)),
+        }
+    }
+}
+
+/// Turns a label in master file format back into its raw bytes, the reverse
+/// of `escape_label`.
+pub fn unescape_label(label: &str) -> Result<Vec<u8>> {
+    let invalid = || DnsError::InvalidInput(format!("Invalid escape in label {}", label));
+
+    let mut output = Vec::with_capacity(label.len());
+    let mut bytes = label.bytes();
+    while let Some(byte) = bytes.next() {
+        if byte != b'\\' {
+            output.push(byte);
+            continue;
+        }
+        match bytes.next() {
+            Some(digit) if digit.is_ascii_digit() => {
+                let mut value = (digit - b'0') as u16;
+                for _ in 0..2 {
+                    match bytes.next() {
+                        Some(digit) if digit.is_ascii_digit() => value = value * 10 + (digit - b'0') as u16,
+                        _ => return Err(invalid()),
+                    }
+                }
+                if value > 0xFF {
+                    return Err(invalid());
+                }
+                output.push(value as u8);
+            },
+            Some(escaped) => output.push(escaped),
+            None => return Err(invalid()),
+        }
+    }
+    Ok(output)
+}
+
+/// Decodes the name starting at `pos` in a message, passing each label to
+/// `label` from left to right, and returns the offset just past the name.
+///
+/// Compression pointers must lead strictly backwards, to a name that appeared
+/// earlier in the message than the labels before the pointer, and a name may
+/// follow at most `MAX_POINTER_HOPS` of them, so decoding always ends.  Names
+/// longer than `MAX_NAME_SIZE` and the reserved 0x40 and 0x80 label types are
+/// rejected.
+pub(crate) fn read_name<F: FnMut(&[u8])>(bytes: &[u8], pos: usize, mut label: F) -> Result<usize> {
+    let mut cur = pos;
+    // Where the labels since the last pointer begin
+    let mut start = pos;
+    let mut end = None;
+    let mut size = 0;
+    let mut hops = 0;
+    loop {
+        let len = *bytes.get(cur).ok_or(DnsError::Truncated { offset: cur })? as usize;
+        match len & 0xC0 {
+            0xC0 => {
+                let next = *bytes.get(cur + 1).ok_or(DnsError::Truncated { offset: cur + 1 })? as usize;
+                let target = (len & 0x3F) << 8 | next;
+                if target > cur {
+                    return Err(DnsError::ForwardPointer { offset: cur });
+                } else if target >= start {
+                    // Back into the labels just read, which would lead here again
+                    return Err(DnsError::PointerLoop { offset: cur });
+                }
+                hops += 1;
+                if hops > MAX_POINTER_HOPS {
+                    return Err(DnsError::PointerLoop { offset: cur });
+                }
+                // Only the first pointer counts towards the end of the name
+                end.get_or_insert(cur + 2);
+                cur = target;
+                start = target;
+            },
+            0x00 => {
+                size += len + 1;
+                if size > MAX_NAME_SIZE {
+                    return Err(DnsError::NameTooLong { offset: cur });
+                }
+                let data = bytes.get(cur + 1..cur + 1 + len).ok_or(DnsError::Truncated { offset: bytes.len() })?;
+                if len == 0 {
+                    return Ok(end.unwrap_or(cur + 1));
+                }
+                label(data);
+                cur += 1 + len;
+            },
+            _ => return Err(DnsError::ReservedLabelType { offset: cur }),
+        }
+    }
+}
+
+impl Default for DnsBuffer {
+    fn default() -> Self {
+        DnsBuffer::new()
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::fs::File;
+    use std::io::Read;
+
     use super::*;
 
     #[test]
@@ -204,7 +429,8 @@ mod tests {
     fn test_read() {
         let mut f = File::open("query.txt").unwrap();
         let mut buf = DnsBuffer::new();
-        f.read(&mut buf.buf).unwrap();
+        let bytes_read = f.read(&mut buf.buf).unwrap();
+        buf.set_len(bytes_read).unwrap();
 
         assert_eq!(buf.get(0).unwrap(), 115);
 
@@ -215,6 +441,66 @@ mod tests {
         assert_eq!(output_str, "google.com");
     }
 
+    #[test]
+    fn test_read_malformed_labels() {
+        let read = |bytes: &[u8], pos: usize| {
+            let mut buf = DnsBuffer::from_bytes(bytes);
+            buf.seek(pos).unwrap();
+            let mut name = String::new();
+            buf.read_label(&mut name).map(|_| (name, buf.pos))
+        };
+
+        // A pointer back to an earlier name, which itself ends in a pointer
+        let bytes = b"\x03com\x00\x06google\xC0\x00\x03www\xC0\x05";
+        assert_eq!(read(bytes, 14).unwrap(), (String::from("www.google.com"), 20));
+
+        assert!(matches!(read(b"\x03www\xC0\x04", 0), Err(DnsError::PointerLoop { offset: 4 })));
+        assert!(matches!(read(b"\xC0\x02\x03com\x00", 0), Err(DnsError::ForwardPointer { offset: 0 })));
+        assert!(matches!(read(b"\x43www\x00", 0), Err(DnsError::ReservedLabelType { offset: 0 })));
+        assert!(matches!(read(b"\x83www\x00", 0), Err(DnsError::ReservedLabelType { offset: 0 })));
+        assert!(matches!(read(b"\x03www", 0), Err(DnsError::Truncated { .. })));
+        assert!(matches!(read(b"\x03ww", 0), Err(DnsError::Truncated { .. })));
+        assert!(matches!(read(b"\xC0", 0), Err(DnsError::Truncated { .. })));
+
+        // 128 one byte labels make a 257 byte name
+        let long = [&b"\x01a"[..]; 128].concat();
+        assert!(matches!(read(&[&long[..], b"\x00"].concat(), 0), Err(DnsError::NameTooLong { .. })));
+        assert_eq!(read(&[&long[..4], b"\x00"].concat(), 0).unwrap().0, "a.a");
+
+        // A long chain of pointers, each to the one before
+        let mut chain = b"\x00".to_vec();
+        for idx in 0..200 {
+            let target: u16 = if idx == 0 { 0 } else { idx * 2 - 1 };
+            chain.extend_from_slice(&(0xC000 | target).to_be_bytes());
+        }
+        assert!(matches!(read(&chain, chain.len() - 2), Err(DnsError::PointerLoop { .. })));
+        assert_eq!(read(&chain, 21).unwrap().0, "");
+    }
+
+    #[test]
+    fn test_escaped_labels() {
+        // A dot, a backslash, a space and a non UTF-8 byte inside labels
+        let name = "a\\.b.back\\\\slash.with\\032space.\\255\\000.example";
+        assert_eq!(split_name(name), vec!["a\\.b", "back\\\\slash", "with\\032space", "\\255\\000", "example"]);
+        assert_eq!(unescape_label("a\\.b").unwrap(), b"a.b");
+        assert_eq!(unescape_label("\\255\\000").unwrap(), b"\xFF\x00");
+        assert!(unescape_label("\\256").is_err());
+        assert!(unescape_label("\\25").is_err());
+        assert!(unescape_label("trailing\\").is_err());
+
+        let mut buf = DnsBuffer::new();
+        buf.write_label(name).unwrap();
+        assert_eq!(buf.get(0).unwrap(), 3);
+        assert_eq!(buf.get_range(1, 3).unwrap(), b"a.b");
+        assert_eq!(buf.get_label(0).unwrap(), name);
+
+        // Escapes are normalized, so equivalent names read back the same way
+        let mut buf = DnsBuffer::new();
+        buf.write_label("\\065\\ b.\\@").unwrap();
+        assert_eq!(buf.get_label(0).unwrap(), "A\\032b.\\@");
+        assert!(buf.write_label("bad\\9").is_err());
+    }
+
     #[test]
     fn test_write() {
         let mut buf = DnsBuffer::new();
@@ -229,4 +515,63 @@ mod tests {
         buf.write_label("google.com").unwrap();
         assert_eq!(buf.get_label(9).unwrap(), "google.com");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_write_compressed() {
+        let mut buf = DnsBuffer::new();
+        buf.write_label("www.google.com").unwrap();
+        assert_eq!(buf.pos, 16);
+
+        // Only the new "mail" label is written, followed by a pointer to google.com
+        buf.write_label("mail.google.com").unwrap();
+        assert_eq!(buf.pos, 23);
+        assert_eq!(buf.get_u16(21).unwrap(), 0xC004);
+
+        // An identical name collapses into a single pointer
+        buf.write_label("www.google.com").unwrap();
+        assert_eq!(buf.pos, 25);
+        assert_eq!(buf.get_u16(23).unwrap(), 0xC000);
+
+        assert_eq!(buf.get_label(0).unwrap(), "www.google.com");
+        assert_eq!(buf.get_label(16).unwrap(), "mail.google.com");
+        assert_eq!(buf.get_label(23).unwrap(), "www.google.com");
+    }
+
+    #[test]
+    fn test_write_uncompressed() {
+        let mut buf = DnsBuffer::new();
+        buf.write_label("google.com").unwrap();
+        buf.write_uncompressed_label("google.com").unwrap();
+        assert_eq!(buf.pos, 24);
+        assert_eq!(buf.get_label(12).unwrap(), "google.com");
+    }
+
+    #[test]
+    fn test_capacity() {
+        let mut buf = DnsBuffer::new();
+        assert_eq!(buf.capacity(), UDP_MAX_SIZE);
+        buf.seek(UDP_MAX_SIZE).unwrap();
+        assert!(buf.write(1).is_err());
+
+        let mut buf = DnsBuffer::with_capacity(TCP_MAX_SIZE);
+        buf.seek(4096).unwrap();
+        buf.write_u16(5).unwrap();
+        assert_eq!(buf.len, 4098);
+        assert_eq!(buf.get_u16(4096).unwrap(), 5);
+    }
+
+    #[test]
+    fn test_read_stops_at_len() {
+        let mut buf = DnsBuffer::from_bytes(&[0, 1, 2]);
+        assert_eq!(buf.capacity(), 3);
+        assert_eq!(buf.read_u16().unwrap(), 1);
+        assert_eq!(buf.read().unwrap(), 2);
+        assert!(buf.read().is_err());
+
+        // Trailing zeros past the received length are not part of the message
+        let mut buf = DnsBuffer::new();
+        buf.set_len(2).unwrap();
+        assert!(buf.get(2).is_err());
+        assert!(buf.set_len(UDP_MAX_SIZE + 1).is_err());
+    }
+}
@@ -0,0 +1,178 @@
+use crate::packet::{DnsPacket, DnsQuestion, DnsRecord, Edns, Opcode, ResponseCode};
+use crate::random::random_u64;
+
+/// Builds a `DnsPacket` one section at a time.  The header counts are taken
+/// from the sections when the packet is built, so they can't disagree with
+/// what gets written.
+#[derive(Debug, Clone)]
+pub struct DnsPacketBuilder {
+    packet: DnsPacket,
+}
+
+impl DnsPacketBuilder {
+    /// Starts a query with a random id and recursion desired.  Responses
+    /// must carry the same id to be accepted by `DnsClient::query`, so a
+    /// random one makes forged responses harder to pass off, see section 9.1
+    /// of https://tools.ietf.org/html/rfc5452
+    pub fn query() -> DnsPacketBuilder {
+        let mut packet = DnsPacket::new();
+        packet.header.id = random_u64() as u16;
+        packet.header.recursion_desired = true;
+        DnsPacketBuilder { packet }
+    }
+
+    /// Starts the response to `query`, copying its id, opcode, questions and
+    /// RD bit.  A query with an OPT record gets one back with the same payload
+    /// size and version, so extended response codes can be sent.
+    pub fn response(query: &DnsPacket) -> DnsPacketBuilder {
+        let mut packet = DnsPacket::new();
+        packet.header.id = query.header.id;
+        packet.header.query_response = true;
+        packet.header.opcode = query.header.opcode;
+        packet.header.recursion_desired = query.header.recursion_desired;
+        packet.questions = query.questions.clone();
+        if let Some(query_edns) = &query.edns {
+            let mut edns = Edns::new(query_edns.udp_payload_size);
+            edns.version = query_edns.version;
+            packet.edns = Some(edns);
+        }
+        DnsPacketBuilder { packet }
+    }
+
+    pub fn id(mut self, id: u16) -> DnsPacketBuilder {
+        self.packet.header.id = id;
+        self
+    }
+
+    pub fn opcode(mut self, opcode: Opcode) -> DnsPacketBuilder {
+        self.packet.header.opcode = opcode;
+        self
+    }
+
+    pub fn authoritative_answer(mut self, authoritative_answer: bool) -> DnsPacketBuilder {
+        self.packet.header.authoritative_answer = authoritative_answer;
+        self
+    }
+
+    pub fn recursion_desired(mut self, recursion_desired: bool) -> DnsPacketBuilder {
+        self.packet.header.recursion_desired = recursion_desired;
+        self
+    }
+
+    pub fn recursion_available(mut self, recursion_available: bool) -> DnsPacketBuilder {
+        self.packet.header.recursion_available = recursion_available;
+        self
+    }
+
+    /// Sets the response code.  Codes above 15 also need an OPT record, from
+    /// the query or `edns`, since their upper bits are carried in it.
+    pub fn response_code(mut self, response_code: ResponseCode) -> DnsPacketBuilder {
+        self.packet.header.response_code = response_code;
+        self
+    }
+
+    pub fn question(mut self, question: DnsQuestion) -> DnsPacketBuilder {
+        self.packet.questions.push(question);
+        self
+    }
+
+    pub fn answer(mut self, record: DnsRecord) -> DnsPacketBuilder {
+        self.packet.answers.push(record);
+        self
+    }
+
+    pub fn authority(mut self, record: DnsRecord) -> DnsPacketBuilder {
+        self.packet.authorities.push(record);
+        self
+    }
+
+    pub fn additional(mut self, record: DnsRecord) -> DnsPacketBuilder {
+        self.packet.additional.push(record);
+        self
+    }
+
+    /// Adds an OPT record advertising `udp_payload_size`, replacing any
+    /// earlier one.
+    pub fn edns(mut self, udp_payload_size: u16) -> DnsPacketBuilder {
+        self.packet.add_edns(udp_payload_size);
+        self
+    }
+
+    pub fn build(mut self) -> DnsPacket {
+        self.packet.update_counts();
+        self.packet
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::DnsBuffer;
+    use crate::packet::RecordType;
+
+    fn question(name: &str) -> DnsQuestion {
+        DnsQuestion::query(name.parse().unwrap(), RecordType::A)
+    }
+
+    #[test]
+    fn test_query() {
+        let query = DnsPacketBuilder::query().question(question("example.com")).edns(1232).build();
+        assert!(query.header.recursion_desired);
+        assert!(!query.header.query_response);
+        assert_eq!(query.header.question_count, 1);
+        assert_eq!(query.header.additional_count, 1);
+        assert_eq!(query.max_udp_size(), 1232);
+
+        // Ids differ from one query to the next, bar the odd collision
+        let ids: Vec<u16> = (0..8).map(|_| DnsPacketBuilder::query().build().header.id).collect();
+        assert!(ids.iter().any(|id| *id != ids[0]));
+    }
+
+    #[test]
+    fn test_response() {
+        let query = DnsPacketBuilder::query().id(4321).recursion_desired(false)
+            .question(question("example.com")).build();
+
+        let record = DnsRecord::a("example.com".parse().unwrap(), 300, "93.184.216.34".parse().unwrap());
+        let response = DnsPacketBuilder::response(&query).authoritative_answer(true)
+            .answer(record.clone()).answer(record.clone()).additional(record).build();
+
+        assert_eq!(response.header.id, 4321);
+        assert!(response.header.query_response);
+        assert!(!response.header.recursion_desired);
+        assert!(response.header.authoritative_answer);
+        assert_eq!(response.questions, query.questions);
+        assert_eq!(response.header.question_count, 1);
+        assert_eq!(response.header.answer_count, 2);
+        assert_eq!(response.header.nameserver_count, 0);
+        assert_eq!(response.header.additional_count, 1);
+
+        let mut buf = DnsBuffer::new();
+        response.write(&mut buf).unwrap();
+        buf.pos = 0;
+        let mut packet = DnsPacket::new();
+        packet.read(&mut buf).unwrap();
+        assert_eq!(packet.header, response.header);
+        assert_eq!(packet.answers.len(), 2);
+        assert_eq!(packet.additional.len(), 1);
+    }
+
+    #[test]
+    fn test_response_edns() {
+        let query = DnsPacketBuilder::query().question(question("example.com")).edns(1232).build();
+        let response = DnsPacketBuilder::response(&query).response_code(ResponseCode::BADCOOKIE).build();
+        assert_eq!(response.edns.as_ref().map(|edns| edns.udp_payload_size), Some(1232));
+        assert_eq!(response.header.additional_count, 1);
+
+        let mut buf = DnsBuffer::new();
+        response.write(&mut buf).unwrap();
+        buf.pos = 0;
+        let mut packet = DnsPacket::new();
+        packet.read(&mut buf).unwrap();
+        assert_eq!(packet.header.response_code, ResponseCode::BADCOOKIE);
+
+        // No OPT record in the query, so none in the response
+        let query = DnsPacketBuilder::query().question(question("example.com")).build();
+        assert!(DnsPacketBuilder::response(&query).build().edns.is_none());
+    }
+}
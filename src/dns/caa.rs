@@ -0,0 +1,154 @@
+use crate::error::Result;
+use crate::packet::{DnsRecord, DnsRecordBody};
+
+// Flag marking a property the CA must understand before issuing
+const CRITICAL_FLAG: u8 = 0x80;
+
+/// Decides whether the CA identified by `issuer` (such as letsencrypt.org) may
+/// issue a certificate for `domain`, following section 3 of
+/// https://tools.ietf.org/html/rfc8659.  `lookup` returns the CAA records for
+/// a single name, and is called for `domain` and then each of its parents
+/// until one of them has CAA records.  Wildcard domains such as *.example.com
+/// are checked against issuewild properties where present.
+pub fn caa_permits<F>(domain: &str, issuer: &str, mut lookup: F) -> Result<bool>
+    where F: FnMut(&str) -> Result<Vec<DnsRecord>> {
+    let domain = domain.trim_end_matches('.');
+    let (wildcard, mut name) = match domain.strip_prefix("*.") {
+        Some(base) => (true, base),
+        None => (false, domain),
+    };
+
+    loop {
+        let properties: Vec<(u8, String, Vec<u8>)> = lookup(name)?.into_iter()
+            .filter_map(|record| match record.body {
+                DnsRecordBody::CAA { flags, tag, value } => Some((flags, tag.to_ascii_lowercase(), value)),
+                _ => None,
+            })
+            .collect();
+
+        if !properties.is_empty() {
+            return Ok(relevant_set_permits(&properties, issuer, wildcard));
+        }
+
+        match name.find('.') {
+            Some(idx) => name = &name[idx + 1..],
+            // No CAA records anywhere up the tree, so any CA may issue
+            None => return Ok(true),
+        }
+    }
+}
+
+fn relevant_set_permits(properties: &[(u8, String, Vec<u8>)], issuer: &str, wildcard: bool) -> bool {
+    // An unknown critical property forbids issuance outright
+    let known = ["issue", "issuewild", "iodef"];
+    if properties.iter().any(|(flags, tag, _)| flags & CRITICAL_FLAG != 0 && !known.contains(&&tag[..])) {
+        return false;
+    }
+
+    let issue: Vec<&Vec<u8>> = properties.iter().filter(|(_, tag, _)| tag == "issue").map(|(_, _, value)| value).collect();
+    let issuewild: Vec<&Vec<u8>> = properties.iter().filter(|(_, tag, _)| tag == "issuewild").map(|(_, _, value)| value).collect();
+    let relevant = if wildcard && !issuewild.is_empty() { issuewild } else { issue };
+
+    // Only iodef or other non-critical properties, so issuance isn't restricted
+    if relevant.is_empty() {
+        return true;
+    }
+
+    relevant.iter().any(|value| issuer_name(value).eq_ignore_ascii_case(issuer.trim_end_matches('.')))
+}
+
+/// The issuer domain name of an issue or issuewild value, without any
+/// parameters after the semicolon.  An empty name means no CA may issue.
+fn issuer_name(value: &[u8]) -> String {
+    let value = String::from_utf8_lossy(value);
+    let name = value.split(';').next().unwrap_or("");
+    String::from(name.trim().trim_end_matches('.'))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::packet::RecordType;
+
+    fn caa_record(flags: u8, tag: &str, value: &str) -> DnsRecord {
+        let mut record = DnsRecord::new();
+        record.preamble.record_type = RecordType::CAA;
+        record.body = DnsRecordBody::CAA { flags, tag: String::from(tag), value: value.as_bytes().to_vec() };
+        record
+    }
+
+    fn check(zone: &HashMap<&str, Vec<(u8, &str, &str)>>, domain: &str, issuer: &str) -> bool {
+        caa_permits(domain, issuer, |name| {
+            Ok(zone.get(name).map(|properties| {
+                properties.iter().map(|(flags, tag, value)| caa_record(*flags, tag, value)).collect()
+            }).unwrap_or_default())
+        }).unwrap()
+    }
+
+    #[test]
+    fn test_walks_up_tree() {
+        let mut zone = HashMap::new();
+        zone.insert("example.com", vec![(0, "issue", "letsencrypt.org"), (0, "iodef", "mailto:security@example.com")]);
+        zone.insert("other.example.com", vec![(0, "issue", "pki.goog; cansignhttpexchanges=yes")]);
+
+        assert!(check(&zone, "www.example.com", "letsencrypt.org"));
+        assert!(check(&zone, "www.example.com", "LetsEncrypt.org."));
+        assert!(!check(&zone, "www.example.com", "pki.goog"));
+        assert!(check(&zone, "a.other.example.com", "pki.goog"));
+        assert!(!check(&zone, "a.other.example.com", "letsencrypt.org"));
+        assert!(check(&zone, "example.net", "anyone.example"));
+    }
+
+    #[test]
+    fn test_wildcard() {
+        let mut zone = HashMap::new();
+        zone.insert("example.com", vec![(0, "issue", "letsencrypt.org"), (0, "issuewild", ";")]);
+        assert!(check(&zone, "example.com", "letsencrypt.org"));
+        assert!(!check(&zone, "*.example.com", "letsencrypt.org"));
+
+        zone.insert("example.com", vec![(0, "issue", "letsencrypt.org")]);
+        assert!(check(&zone, "*.example.com", "letsencrypt.org"));
+    }
+
+    #[test]
+    fn test_critical_flag() {
+        let mut zone = HashMap::new();
+        zone.insert("example.com", vec![(0, "issue", "letsencrypt.org"), (128, "tbs", "unknown")]);
+        assert!(!check(&zone, "example.com", "letsencrypt.org"));
+
+        zone.insert("example.com", vec![(0, "issue", "letsencrypt.org"), (0, "tbs", "unknown")]);
+        assert!(check(&zone, "example.com", "letsencrypt.org"));
+
+        zone.insert("example.com", vec![(0, "iodef", "mailto:security@example.com")]);
+        assert!(check(&zone, "example.com", "letsencrypt.org"));
+
+        zone.insert("example.com", vec![(0, "issue", "letsencrypt.org"), (128, "is-sue", "letsencrypt.org")]);
+        assert!(!check(&zone, "example.com", "letsencrypt.org"));
+    }
+
+    #[test]
+    fn test_write_caa() {
+        use crate::buffer::DnsBuffer;
+
+        let record = caa_record(128, "issue", "letsencrypt.org");
+        let mut buf = DnsBuffer::new();
+        record.write(&mut buf).unwrap();
+        buf.pos = 0;
+
+        let mut new_record = DnsRecord::new();
+        new_record.read(&mut buf).unwrap();
+        assert_eq!(new_record.body, record.body);
+        assert_eq!(new_record.body.to_string(), "128\tissue\t\"letsencrypt.org\"");
+
+        // A tag outside the RFC 8659 syntax doesn't stop the record reading
+        let record = caa_record(128, "is-sue", "letsencrypt.org");
+        let mut buf = DnsBuffer::new();
+        record.write(&mut buf).unwrap();
+        buf.pos = 0;
+        let mut new_record = DnsRecord::new();
+        new_record.read(&mut buf).unwrap();
+        assert_eq!(new_record.body, record.body);
+    }
+}
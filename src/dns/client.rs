@@ -1,13 +1,22 @@
 
-use std::io::Result;
-use std::net::UdpSocket;
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream, ToSocketAddrs, UdpSocket};
+use std::time::{SystemTime, UNIX_EPOCH};
 
-use crate::buffer::DnsBuffer;
-use crate::packet::DnsPacket;
+use crate::error::{DnsError, Result};
+use crate::buffer::{DnsBuffer, EDNS_UDP_SIZE, TCP_MAX_SIZE};
+use crate::packet::{DnsPacket, DnsRecordBody, RecordType};
+use crate::srv::order_srv_targets;
+use crate::caa::caa_permits;
+use crate::dnssec::{root_trust_anchors, validate, Verdict};
 
 pub struct DnsClient<'a> {
     pub server: (&'a str, u16),
     pub socket: UdpSocket,
+    // Largest response accepted, which should match the size advertised through EDNS
+    pub udp_payload_size: u16,
+    // DS records for the root zone keys that DNSSEC validation starts from
+    pub trust_anchors: Vec<DnsRecordBody>,
 }
 
 /// Queries the Google DNS server using a provided DNS packet.
@@ -16,16 +25,206 @@ impl DnsClient<'_> {
         Ok(DnsClient {
             server: ("8.8.8.8", 53),
             socket: UdpSocket::bind("0.0.0.0:43210")?,
+            udp_payload_size: EDNS_UDP_SIZE as u16,
+            trust_anchors: root_trust_anchors(),
         })
     }
 
+    /// Sends the query in `buf` and waits for the server's response.
+    /// Datagrams from any other address, or with an id other than the
+    /// query's, are dropped, so a forged response has to guess both.  See
+    /// section 9.1 of https://tools.ietf.org/html/rfc5452
+    ///
+    /// A truncated response is thrown away and the query retried over TCP,
+    /// as section 4.2.2 of https://www.ietf.org/rfc/rfc1035.txt describes.
     pub fn query(&self, buf: &DnsBuffer) -> Result<DnsPacket> {
+        let id = u16::from_be_bytes([buf.get(0)?, buf.get(1)?]);
+        let servers: Vec<SocketAddr> = self.server.to_socket_addrs()?.collect();
         let _bytes_written = self.socket.send_to(&buf.buf[0..buf.pos], self.server)?;
-        let mut response_buf = DnsBuffer::new();
-        let mut response_packet = DnsPacket::new();
-        let (_bytes_read, _origin) = self.socket.recv_from(&mut response_buf.buf)?;
 
+        loop {
+            let mut response_buf = DnsBuffer::with_capacity(self.udp_payload_size as usize);
+            let (bytes_read, origin) = self.socket.recv_from(&mut response_buf.buf)?;
+            if !servers.contains(&origin) || bytes_read < 2 {
+                continue;
+            }
+            if u16::from_be_bytes([response_buf.buf[0], response_buf.buf[1]]) != id {
+                continue;
+            }
+            response_buf.set_len(bytes_read)?;
+
+            let mut response_packet = DnsPacket::new();
+            response_packet.read(&mut response_buf)?;
+            if response_packet.header.truncated_message {
+                return self.query_tcp(buf, id);
+            }
+            return Ok(response_packet);
+        }
+    }
+
+    /// Sends the query in `buf` over TCP, where each message is preceded by
+    /// its length in two bytes, and reads back the response.
+    fn query_tcp(&self, buf: &DnsBuffer, id: u16) -> Result<DnsPacket> {
+        let mut stream = TcpStream::connect(self.server)?;
+        stream.set_read_timeout(self.socket.read_timeout()?)?;
+        let query = &buf.buf[0..buf.pos];
+        stream.write_all(&(query.len() as u16).to_be_bytes())?;
+        stream.write_all(query)?;
+
+        let mut length = [0_u8; 2];
+        stream.read_exact(&mut length)?;
+        let mut response_buf = DnsBuffer::with_capacity(TCP_MAX_SIZE);
+        let bytes_read = u16::from_be_bytes(length) as usize;
+        stream.read_exact(&mut response_buf.buf[..bytes_read])?;
+        response_buf.set_len(bytes_read)?;
+
+        let mut response_packet = DnsPacket::new();
         response_packet.read(&mut response_buf)?;
+        if response_packet.header.id != id {
+            return Err(DnsError::Malformed { offset: 0, message: String::from("Response id doesn't match the query") });
+        }
         Ok(response_packet)
     }
-}
\ No newline at end of file
+
+    /// Looks up the SRV records for `_service._proto.name`, returning the
+    /// host and port pairs in the order they should be tried.
+    pub fn lookup_srv(&self, service: &str, proto: &str, name: &str) -> Result<Vec<(String, u16)>> {
+        let query_name = format!("_{}._{}.{}", service.trim_start_matches('_'), proto.trim_start_matches('_'), name);
+        let mut packet = DnsPacket::from_query(&query_name, "SRV")?;
+        packet.add_edns(self.udp_payload_size);
+        let mut buf = DnsBuffer::new();
+        packet.write(&mut buf)?;
+
+        let response = self.query(&buf)?;
+        Ok(order_srv_targets(&response.answers))
+    }
+
+    /// Checks the CAA records for `domain` and its parents to see whether the
+    /// CA identified by `issuer` may issue a certificate for it.
+    pub fn check_caa(&self, domain: &str, issuer: &str) -> Result<bool> {
+        caa_permits(domain, issuer, |name| {
+            let mut packet = DnsPacket::from_query(name, "CAA")?;
+            packet.add_edns(self.udp_payload_size);
+            let mut buf = DnsBuffer::new();
+            packet.write(&mut buf)?;
+            Ok(self.query(&buf)?.answers)
+        })
+    }
+
+    /// Looks up `hostname` with DNSSEC records included, and validates the
+    /// answer against `trust_anchors` by fetching DS and DNSKEY records all
+    /// the way down from the root.
+    pub fn query_secure(&self, hostname: &str, query_type: &str) -> Result<(DnsPacket, Verdict)> {
+        let response = self.query_dnssec(hostname, query_type.parse()?)?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|elapsed| elapsed.as_secs()).unwrap_or(0);
+        let verdict = validate(&response, &self.trust_anchors, now as u32, |name, record_type| {
+            self.query_dnssec(name, record_type)
+        });
+        Ok((response, verdict))
+    }
+
+    fn query_dnssec(&self, name: &str, record_type: RecordType) -> Result<DnsPacket> {
+        let mut packet = DnsPacket::from_query(name, &record_type.to_string())?;
+        packet.add_edns(self.udp_payload_size);
+        if let Some(edns) = &mut packet.edns {
+            edns.dnssec_ok = true;
+        }
+        // Checking disabled, so the server hands over bogus data for us to
+        // judge rather than answering SERVFAIL
+        packet.header.z |= 1;
+        let mut buf = DnsBuffer::new();
+        packet.write(&mut buf)?;
+        self.query(&buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::builder::DnsPacketBuilder;
+    use crate::packet::{DnsRecord, ResponseCode};
+
+    fn datagram(packet: &DnsPacket) -> Vec<u8> {
+        let mut buf = DnsBuffer::new();
+        packet.write(&mut buf).unwrap();
+        buf.as_bytes().to_vec()
+    }
+
+    #[test]
+    fn test_forged_responses() {
+        let server = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let forger = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let client = DnsClient {
+            server: ("127.0.0.1", server.local_addr().unwrap().port()),
+            socket: UdpSocket::bind("127.0.0.1:0").unwrap(),
+            udp_payload_size: EDNS_UDP_SIZE as u16,
+            trust_anchors: Vec::new(),
+        };
+        client.socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let client_addr = client.socket.local_addr().unwrap();
+
+        let query = DnsPacket::from_query("example.com", "A").unwrap();
+        let response = |id: u16, code: ResponseCode| DnsPacketBuilder::response(&query).id(id).response_code(code).build();
+        // The right id from the wrong address, then the wrong id from the
+        // right address, both queued ahead of the genuine response
+        forger.send_to(&datagram(&response(query.header.id, ResponseCode::NXDOMAIN)), client_addr).unwrap();
+        server.send_to(&datagram(&response(query.header.id.wrapping_add(1), ResponseCode::REFUSED)), client_addr).unwrap();
+        server.send_to(&datagram(&response(query.header.id, ResponseCode::NOERROR)), client_addr).unwrap();
+
+        let mut buf = DnsBuffer::new();
+        query.write(&mut buf).unwrap();
+        let answer = client.query(&buf).unwrap();
+        assert_eq!(answer.header.id, query.header.id);
+        assert_eq!(answer.header.response_code, ResponseCode::NOERROR);
+    }
+
+    #[test]
+    fn test_truncated_response() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = UdpSocket::bind(("127.0.0.1", port)).unwrap();
+        let client = DnsClient {
+            server: ("127.0.0.1", port),
+            socket: UdpSocket::bind("127.0.0.1:0").unwrap(),
+            udp_payload_size: EDNS_UDP_SIZE as u16,
+            trust_anchors: Vec::new(),
+        };
+        client.socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+        let query = DnsPacket::from_query("example.com", "A").unwrap();
+        let mut truncated = DnsPacketBuilder::response(&query).build();
+        truncated.header.truncated_message = true;
+        server.send_to(&datagram(&truncated), client.socket.local_addr().unwrap()).unwrap();
+
+        // The full answer only comes over TCP
+        let tcp_server = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut length = [0_u8; 2];
+            stream.read_exact(&mut length).unwrap();
+            let mut bytes = vec![0_u8; u16::from_be_bytes(length) as usize];
+            stream.read_exact(&mut bytes).unwrap();
+            let mut query = DnsPacket::new();
+            query.read(&mut DnsBuffer::from_bytes(&bytes)).unwrap();
+
+            let address = "192.0.2.1".parse().unwrap();
+            let response = DnsPacketBuilder::response(&query)
+                .answer(DnsRecord::a("example.com".parse().unwrap(), 300, address))
+                .build();
+            let bytes = datagram(&response);
+            stream.write_all(&(bytes.len() as u16).to_be_bytes()).unwrap();
+            stream.write_all(&bytes).unwrap();
+        });
+
+        let mut buf = DnsBuffer::new();
+        query.write(&mut buf).unwrap();
+        let answer = client.query(&buf).unwrap();
+        tcp_server.join().unwrap();
+        assert!(!answer.header.truncated_message);
+        assert_eq!(answer.header.id, query.header.id);
+        assert_eq!(answer.answers.len(), 1);
+    }
+}
@@ -0,0 +1,942 @@
+use std::collections::HashMap;
+
+use ring::{digest, signature};
+
+use crate::buffer::{DnsBuffer, TCP_MAX_SIZE};
+use crate::encoding::{base32hex_encode, hex_decode};
+use crate::error::Result;
+use crate::name::DnsName;
+use crate::packet::{DnsPacket, DnsRecord, DnsRecordBody, RecordType};
+
+// Signing algorithms we can verify, from
+// https://www.iana.org/assignments/dns-sec-alg-numbers
+const RSASHA256: u8 = 8;
+const ECDSAP256SHA256: u8 = 13;
+const ED25519: u8 = 15;
+
+// DS digest types
+const SHA1: u8 = 1;
+const SHA256: u8 = 2;
+const SHA384: u8 = 4;
+
+// DNSKEY flag marking a key that may sign the zone's records
+const ZONE_KEY_FLAG: u16 = 0x0100;
+// NSEC3 flag marking a span that may hide unsigned delegations
+const OPT_OUT_FLAG: u8 = 0x01;
+// Far more NSEC3 iterations than anyone needs, see https://tools.ietf.org/html/rfc9276
+const MAX_NSEC3_ITERATIONS: u16 = 150;
+// Longer CNAME chains than this are treated as loops
+const MAX_CNAME_HOPS: usize = 16;
+
+/// The security status of a response, as defined in section 5 of
+/// https://tools.ietf.org/html/rfc4033
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    // Every RRset was signed by a chain of keys leading back to a trust anchor
+    Secure,
+    // The chain of trust proves the answer comes from an unsigned zone
+    Insecure,
+    // Signatures are missing, invalid or expired where the chain requires them
+    Bogus,
+    // The chain of trust couldn't be followed, for example when a lookup fails
+    Indeterminate,
+}
+
+impl std::fmt::Display for Verdict {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            Verdict::Secure => { write!(f, "SECURE")?; },
+            Verdict::Insecure => { write!(f, "INSECURE")?; },
+            Verdict::Bogus => { write!(f, "BOGUS")?; },
+            Verdict::Indeterminate => { write!(f, "INDETERMINATE")?; },
+        }
+        Ok(())
+    }
+}
+
+/// The DS records of the root zone KSKs, as published by IANA at
+/// https://data.iana.org/root-anchors/root-anchors.xml
+pub fn root_trust_anchors() -> Vec<DnsRecordBody> {
+    let anchors = [
+        (20326, "E06D44B80B8F1D39A95C0B0D7C65D08458E880409BBC683457104237C7F8EC8D"),
+        (38696, "683D2D0ACB8C9B712A1948B27F741219298D0A450D612C483AF444A4C0FB2B16"),
+    ];
+    anchors.iter()
+        .map(|(key_tag, digest)| DnsRecordBody::DS {
+            key_tag: *key_tag,
+            algorithm: RSASHA256,
+            digest_type: SHA256,
+            digest: hex_decode(digest).unwrap(),
+        })
+        .collect()
+}
+
+/// Validates `response` by following the chain of trust down from the root
+/// zone, whose keys must match one of the DS records in `anchors`.  `lookup`
+/// fetches the DS and DNSKEY records along the way, and should send queries
+/// with the DO and CD bits set so signatures are returned even when the
+/// upstream resolver considers them bogus.  `now` is the current time in
+/// seconds since the epoch, used to check signature validity periods.
+///
+/// Validation follows section 5 of https://tools.ietf.org/html/rfc4035, and
+/// section 8 of https://tools.ietf.org/html/rfc5155 for NSEC3 denials.
+pub fn validate<F>(response: &DnsPacket, anchors: &[DnsRecordBody], now: u32, lookup: F) -> Verdict
+    where F: FnMut(&str, RecordType) -> Result<DnsPacket> {
+    let mut validator = Validator { anchors, now, lookup, responses: HashMap::new() };
+    match validator.check_response(response) {
+        Ok(()) => Verdict::Secure,
+        Err(verdict) => verdict,
+    }
+}
+
+// Anything short of a secure result stops validation with that verdict
+type Chain<T> = std::result::Result<T, Verdict>;
+
+/// A zone whose DNSKEY RRset has been authenticated.
+#[derive(Clone)]
+struct Zone {
+    name: DnsName,
+    keys: Vec<DnsRecordBody>,
+}
+
+/// What a set of NSEC or NSEC3 records proves about a name.
+#[derive(Debug, PartialEq, Eq)]
+enum Denial {
+    // The name exists, but only with these types
+    NoData(Vec<RecordType>),
+    NxDomain,
+    // The name falls in an opt-out span, so may be an unsigned delegation
+    OptOut,
+}
+
+struct Validator<'a, F> {
+    anchors: &'a [DnsRecordBody],
+    now: u32,
+    lookup: F,
+    // Responses to the DS and DNSKEY queries made so far
+    responses: HashMap<(DnsName, RecordType), DnsPacket>,
+}
+
+impl<F> Validator<'_, F> where F: FnMut(&str, RecordType) -> Result<DnsPacket> {
+    fn check_response(&mut self, response: &DnsPacket) -> Chain<()> {
+        let question = response.questions.first().ok_or(Verdict::Indeterminate)?;
+        let mut insecure = false;
+
+        for rrset in rrsets(&response.answers) {
+            // The DS RRset lives on the parent side of a zone cut
+            let owner = &rrset[0].preamble.name;
+            let zone = if rrset[0].preamble.record_type == RecordType::DS {
+                self.zone_for(&owner.parent().unwrap_or_else(DnsName::root))
+            } else {
+                self.zone_for(owner)
+            };
+            let verified = zone.and_then(|zone| {
+                let labels = verify_rrset(&rrset, &response.answers, &zone, self.now)?;
+                // An answer expanded from a wildcard needs proof that no closer
+                // name exists, see section 5.3.4 of RFC 4035
+                if labels < label_count(owner) {
+                    let next_closer = DnsName::from_labels(owner.labels().skip(owner.label_count() - labels - 1))
+                        .map_err(|_| Verdict::Bogus)?;
+                    if !DenialRecords::new(&response.authorities, &zone, self.now)?.proves_absent(&next_closer)? {
+                        return Err(Verdict::Bogus);
+                    }
+                }
+                Ok(())
+            });
+            match verified {
+                Ok(()) => {},
+                Err(Verdict::Insecure) => insecure = true,
+                Err(verdict) => return Err(verdict),
+            }
+        }
+
+        // Follow any CNAMEs to the name that should hold the answer, and
+        // check the denial of existence if it isn't there.  A chain that loops
+        // back on itself never leads to an answer.
+        let mut target = question.name.clone();
+        let mut visited = vec![target.clone()];
+        while let Some(DnsRecordBody::CNAME { name }) = find_record(&response.answers, &target, RecordType::CNAME) {
+            if visited.contains(name) || visited.len() > MAX_CNAME_HOPS {
+                return Err(Verdict::Bogus);
+            }
+            visited.push(name.clone());
+            target = name.clone();
+        }
+        if find_record(&response.answers, &target, question.record_type).is_none() {
+            let zone = self.zone_for(&target)?;
+            match prove_denial(&response.authorities, &target, &zone, self.now)? {
+                Denial::NoData(types) if types.contains(&question.record_type) || types.contains(&RecordType::CNAME) => {
+                    return Err(Verdict::Bogus);
+                },
+                Denial::OptOut => insecure = true,
+                _ => {},
+            }
+        }
+
+        if insecure {
+            return Err(Verdict::Insecure);
+        }
+        Ok(())
+    }
+
+    /// Walks down from the root through each ancestor of `name`, returning the
+    /// closest enclosing zone once its keys have been authenticated.  Returns
+    /// `Insecure` as soon as a delegation is proven to be unsigned.
+    fn zone_for(&mut self, name: &DnsName) -> Chain<Zone> {
+        let mut zone = self.root_zone()?;
+
+        for candidate in ancestors(name) {
+            let response = self.fetch(&candidate, RecordType::DS)?;
+            let ds_set: Vec<&DnsRecord> = response.answers.iter()
+                .filter(|record| record.preamble.record_type == RecordType::DS && record.preamble.name == candidate)
+                .collect();
+
+            if !ds_set.is_empty() {
+                verify_rrset(&ds_set, &response.answers, &zone, self.now)?;
+                let ds_bodies: Vec<DnsRecordBody> = ds_set.iter().map(|record| record.body.clone()).collect();
+                zone = self.trusted_zone(&candidate, &ds_bodies)?;
+                continue;
+            }
+
+            match prove_denial(&response.authorities, &candidate, &zone, self.now)? {
+                // A delegation without a DS record leads to an unsigned zone, see
+                // section 5.2 of https://tools.ietf.org/html/rfc4035 and section
+                // 8.9 of https://tools.ietf.org/html/rfc5155.  A proof showing a
+                // DS record that wasn't in the answer means it was stripped.
+                Denial::NoData(types) => {
+                    if types.contains(&RecordType::DS) {
+                        return Err(Verdict::Bogus);
+                    }
+                    if types.contains(&RecordType::NS) && !types.contains(&RecordType::SOA) {
+                        return Err(Verdict::Insecure);
+                    }
+                },
+                Denial::OptOut => return Err(Verdict::Insecure),
+                // Nothing exists at or below this name, so the current zone holds the denial
+                Denial::NxDomain => break,
+            }
+        }
+        Ok(zone)
+    }
+
+    fn root_zone(&mut self) -> Chain<Zone> {
+        if self.anchors.is_empty() {
+            return Err(Verdict::Indeterminate);
+        }
+        let anchors = self.anchors.to_vec();
+        self.trusted_zone(&DnsName::root(), &anchors)
+    }
+
+    /// Fetches the DNSKEY RRset of `name`, and authenticates it with a key
+    /// matching one of the DS records in `ds_set`.
+    fn trusted_zone(&mut self, name: &DnsName, ds_set: &[DnsRecordBody]) -> Chain<Zone> {
+        // A zone signed only with algorithms we don't know is treated as unsigned,
+        // see section 5.2 of https://tools.ietf.org/html/rfc4035
+        let supported: Vec<&DnsRecordBody> = ds_set.iter()
+            .filter(|ds| match ds {
+                DnsRecordBody::DS { algorithm, digest_type, .. } => supported_algorithm(*algorithm) && ds_digest_type(*digest_type).is_some(),
+                _ => false,
+            })
+            .collect();
+        if supported.is_empty() {
+            return Err(Verdict::Insecure);
+        }
+
+        let response = self.fetch(name, RecordType::DNSKEY)?;
+        let dnskeys: Vec<&DnsRecord> = response.answers.iter()
+            .filter(|record| record.preamble.record_type == RecordType::DNSKEY && record.preamble.name == *name)
+            .collect();
+        let signing_keys: Vec<DnsRecordBody> = dnskeys.iter()
+            .map(|record| record.body.clone())
+            .filter(|key| supported.iter().any(|ds| ds_matches(name, key, ds)))
+            .collect();
+        if signing_keys.is_empty() {
+            return Err(Verdict::Bogus);
+        }
+
+        // The DNSKEY RRset must be signed by a key the parent vouches for
+        let key_signing_zone = Zone { name: name.clone(), keys: signing_keys };
+        verify_rrset(&dnskeys, &response.answers, &key_signing_zone, self.now)?;
+
+        Ok(Zone {
+            name: name.clone(),
+            keys: dnskeys.iter().map(|record| record.body.clone()).collect(),
+        })
+    }
+
+    fn fetch(&mut self, name: &DnsName, record_type: RecordType) -> Chain<DnsPacket> {
+        let key = (name.clone(), record_type);
+        if let Some(response) = self.responses.get(&key) {
+            return Ok(response.clone());
+        }
+        let response = (self.lookup)(&name.to_string(), record_type).map_err(|_| Verdict::Indeterminate)?;
+        self.responses.insert(key, response.clone());
+        Ok(response)
+    }
+}
+
+/// Checks that one of the RRSIGs in `records` covering `rrset` was made by a
+/// key of `zone` and is currently valid.  Returns the label count of that
+/// RRSIG, which is less than the owner's when the RRset was expanded from a
+/// wildcard.
+fn verify_rrset(rrset: &[&DnsRecord], records: &[DnsRecord], zone: &Zone, now: u32) -> Chain<usize> {
+    let owner = &rrset[0].preamble.name;
+    let record_type = rrset[0].preamble.record_type;
+
+    for sig in records.iter().filter(|record| record.preamble.name == *owner) {
+        let (algorithm, labels, expiration, inception, key_tag, signer_name, signature) = match &sig.body {
+            DnsRecordBody::RRSIG { type_covered, algorithm, labels, expiration, inception, key_tag, signer_name, signature, .. }
+                if *type_covered == record_type => (*algorithm, *labels, *expiration, *inception, *key_tag, signer_name, signature),
+            _ => continue,
+        };
+        if *signer_name != zone.name || !in_validity_period(inception, expiration, now) {
+            continue;
+        }
+        if labels as usize > label_count(owner) {
+            continue;
+        }
+
+        let data = match signed_data(&sig.body, rrset) {
+            Ok(data) => data,
+            Err(_) => continue,
+        };
+        let verified = zone.keys.iter().any(|key| match key {
+            DnsRecordBody::DNSKEY { flags, protocol: 3, algorithm: key_algorithm, public_key }
+                if flags & ZONE_KEY_FLAG != 0 && *key_algorithm == algorithm && key.key_tag() == Some(key_tag) => {
+                verify_signature(algorithm, public_key, &data, signature)
+            },
+            _ => false,
+        });
+        if verified {
+            return Ok(labels as usize);
+        }
+    }
+    Err(Verdict::Bogus)
+}
+
+/// Looks through the NSEC and NSEC3 records in `records`, which must be signed
+/// by `zone`, for proof of what exists at `name`.
+fn prove_denial(records: &[DnsRecord], name: &DnsName, zone: &Zone, now: u32) -> Chain<Denial> {
+    DenialRecords::new(records, zone, now)?.prove(name, zone)
+}
+
+/// An NSEC record, showing that no names exist between its owner and the
+/// next name in the zone.
+struct Nsec<'a> {
+    owner: &'a DnsName,
+    next: &'a DnsName,
+    types: &'a [RecordType],
+}
+
+impl Nsec<'_> {
+    /// True if `name` falls between the owner and the next name.  Names are
+    /// ordered canonically, and the last NSEC in a zone wraps around to the
+    /// apex.
+    fn covers(&self, name: &DnsName) -> bool {
+        self.owner < name && (name < self.next || self.next <= self.owner)
+    }
+}
+
+/// An NSEC3 record, which does the same for the hashes of names.
+struct Nsec3<'a> {
+    owner_hash: String,
+    next_hash: String,
+    salt: &'a [u8],
+    iterations: u16,
+    opt_out: bool,
+    types: &'a [RecordType],
+}
+
+impl Nsec3<'_> {
+    fn hash(&self, name: &DnsName) -> Chain<String> {
+        nsec3_hash(name, self.salt, self.iterations).map_err(|_| Verdict::Bogus)
+    }
+
+    fn matches(&self, name: &DnsName) -> Chain<bool> {
+        Ok(self.hash(name)? == self.owner_hash)
+    }
+
+    fn covers(&self, name: &DnsName) -> Chain<bool> {
+        let hashed = self.hash(name)?;
+        if self.owner_hash < self.next_hash {
+            Ok(self.owner_hash < hashed && hashed < self.next_hash)
+        } else {
+            Ok(self.owner_hash < hashed || hashed < self.next_hash)
+        }
+    }
+}
+
+/// The NSEC and NSEC3 records of a response, once their signatures have been
+/// checked.
+struct DenialRecords<'a> {
+    nsecs: Vec<Nsec<'a>>,
+    nsec3s: Vec<Nsec3<'a>>,
+}
+
+impl<'a> DenialRecords<'a> {
+    fn new(records: &'a [DnsRecord], zone: &Zone, now: u32) -> Chain<DenialRecords<'a>> {
+        let mut denials = DenialRecords { nsecs: Vec::new(), nsec3s: Vec::new() };
+        for rrset in rrsets(records) {
+            let record = rrset[0];
+            match &record.body {
+                DnsRecordBody::NSEC { next_domain, types } => {
+                    verify_rrset(&rrset, records, zone, now)?;
+                    denials.nsecs.push(Nsec { owner: &record.preamble.name, next: next_domain, types });
+                },
+                DnsRecordBody::NSEC3 { hash_algorithm: 1, flags, iterations, salt, next_hashed_owner, types } => {
+                    if *iterations > MAX_NSEC3_ITERATIONS {
+                        return Err(Verdict::Insecure);
+                    }
+                    verify_rrset(&rrset, records, zone, now)?;
+                    denials.nsec3s.push(Nsec3 {
+                        owner_hash: record.preamble.name.labels().next()
+                            .map_or(String::new(), |label| String::from_utf8_lossy(label).to_ascii_uppercase()),
+                        next_hash: base32hex_encode(next_hashed_owner),
+                        salt,
+                        iterations: *iterations,
+                        opt_out: flags & OPT_OUT_FLAG != 0,
+                        types,
+                    });
+                },
+                _ => {},
+            }
+        }
+        Ok(denials)
+    }
+
+    fn prove(&self, name: &DnsName, zone: &Zone) -> Chain<Denial> {
+        if !self.nsecs.is_empty() {
+            self.prove_with_nsec(name)
+        } else {
+            self.prove_with_nsec3(name, zone)
+        }
+    }
+
+    /// Proves a name doesn't exist, or which types it has, as in section 5.4
+    /// of RFC 4035.  A missing name also needs proof that no wildcard could
+    /// have answered in its place.
+    fn prove_with_nsec(&self, name: &DnsName) -> Chain<Denial> {
+        if let Some(nsec) = self.nsecs.iter().find(|nsec| nsec.owner == name) {
+            return Ok(Denial::NoData(nsec.types.to_vec()));
+        }
+        let cover = self.nsecs.iter().find(|nsec| nsec.covers(name)).ok_or(Verdict::Bogus)?;
+        // A name with descendants exists, even though it owns no records
+        if cover.next.is_subdomain_of(name) {
+            return Ok(Denial::NoData(Vec::new()));
+        }
+
+        // The closest encloser is the longest ancestor the name shares with
+        // either end of the NSEC that covers it
+        let encloser = ancestors(name).into_iter().rev()
+            .find(|ancestor| cover.owner.is_subdomain_of(ancestor) || cover.next.is_subdomain_of(ancestor))
+            .unwrap_or_else(DnsName::root);
+        let wildcard = encloser.child(b"*").map_err(|_| Verdict::Bogus)?;
+        if let Some(nsec) = self.nsecs.iter().find(|nsec| *nsec.owner == wildcard) {
+            return Ok(Denial::NoData(nsec.types.to_vec()));
+        }
+        if self.nsecs.iter().any(|nsec| nsec.covers(&wildcard)) {
+            return Ok(Denial::NxDomain);
+        }
+        Err(Verdict::Bogus)
+    }
+
+    /// Proves a name doesn't exist, or which types it has, as in sections 8.4
+    /// to 8.7 of RFC 5155.
+    fn prove_with_nsec3(&self, name: &DnsName, zone: &Zone) -> Chain<Denial> {
+        if let Some(nsec3) = self.matching_nsec3(name)? {
+            return Ok(Denial::NoData(nsec3.types.to_vec()));
+        }
+
+        // The closest encloser proof of section 8.3: an ancestor that exists,
+        // and a covered next closer name one label below it
+        let mut encloser = name.parent().ok_or(Verdict::Bogus)?;
+        let types = loop {
+            if !encloser.is_subdomain_of(&zone.name) {
+                return Err(Verdict::Bogus);
+            }
+            if let Some(nsec3) = self.matching_nsec3(&encloser)? {
+                break nsec3.types;
+            }
+            encloser = encloser.parent().ok_or(Verdict::Bogus)?;
+        };
+        // Below a delegation, the zone has no authority to deny anything
+        if types.contains(&RecordType::NS) && !types.contains(&RecordType::SOA) {
+            return Err(Verdict::Bogus);
+        }
+        let next_closer = DnsName::from_labels(name.labels().skip(name.label_count() - encloser.label_count() - 1))
+            .map_err(|_| Verdict::Bogus)?;
+        let cover = self.covering_nsec3(&next_closer)?.ok_or(Verdict::Bogus)?;
+        // The next closer name may be an unsigned delegation
+        if cover.opt_out {
+            return Ok(Denial::OptOut);
+        }
+
+        let wildcard = encloser.child(b"*").map_err(|_| Verdict::Bogus)?;
+        if let Some(nsec3) = self.matching_nsec3(&wildcard)? {
+            return Ok(Denial::NoData(nsec3.types.to_vec()));
+        }
+        if self.covering_nsec3(&wildcard)?.is_some() {
+            return Ok(Denial::NxDomain);
+        }
+        Err(Verdict::Bogus)
+    }
+
+    /// True if an NSEC or NSEC3 record shows that nothing exists at `name`,
+    /// or anywhere below it.
+    fn proves_absent(&self, name: &DnsName) -> Chain<bool> {
+        if self.nsecs.iter().any(|nsec| nsec.covers(name) && !nsec.next.is_subdomain_of(name)) {
+            return Ok(true);
+        }
+        Ok(self.covering_nsec3(name)?.is_some())
+    }
+
+    fn matching_nsec3(&self, name: &DnsName) -> Chain<Option<&Nsec3<'a>>> {
+        for nsec3 in &self.nsec3s {
+            if nsec3.matches(name)? {
+                return Ok(Some(nsec3));
+            }
+        }
+        Ok(None)
+    }
+
+    fn covering_nsec3(&self, name: &DnsName) -> Chain<Option<&Nsec3<'a>>> {
+        for nsec3 in &self.nsec3s {
+            if nsec3.covers(name)? {
+                return Ok(Some(nsec3));
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Builds the data an RRSIG signs: its own RDATA up to the signature, then
+/// every record of the RRset in canonical form and order.  See section 3.1.8.1
+/// of https://tools.ietf.org/html/rfc4034
+fn signed_data(sig: &DnsRecordBody, rrset: &[&DnsRecord]) -> Result<Vec<u8>> {
+    let mut buf = canonical_buffer();
+    if let DnsRecordBody::RRSIG { type_covered, algorithm, labels, original_ttl, expiration, inception, key_tag, signer_name, .. } = sig {
+        buf.write_u16(type_covered.to_num())?;
+        buf.write(*algorithm)?;
+        buf.write(*labels)?;
+        buf.write_u32(*original_ttl)?;
+        buf.write_u32(*expiration)?;
+        buf.write_u32(*inception)?;
+        buf.write_u16(*key_tag)?;
+        signer_name.to_lowercase().write(&mut buf)?;
+
+        // Records expanded from a wildcard are signed with the wildcard as owner
+        let owner = rrset[0].preamble.name.to_lowercase();
+        let owner = if (*labels as usize) < owner.label_count() {
+            let closest = owner.labels().skip(owner.label_count() - *labels as usize);
+            DnsName::from_labels(std::iter::once(&b"*"[..]).chain(closest))?
+        } else {
+            owner
+        };
+
+        let mut rdatas = Vec::with_capacity(rrset.len());
+        for record in rrset {
+            rdatas.push(canonical_rdata(&record.body)?);
+        }
+        rdatas.sort();
+        rdatas.dedup();
+
+        for rdata in rdatas {
+            owner.write(&mut buf)?;
+            buf.write_u16(rrset[0].preamble.record_type.to_num())?;
+            buf.write_u16(rrset[0].preamble.record_class.to_num())?;
+            buf.write_u32(*original_ttl)?;
+            buf.write_u16(rdata.len() as u16)?;
+            buf.write_bytes(&rdata)?;
+        }
+    }
+    Ok(buf.as_bytes().to_vec())
+}
+
+/// Writes RDATA in canonical form, with names uncompressed and lowercased
+/// for the record types listed in section 5.1 of https://tools.ietf.org/html/rfc6840
+fn canonical_rdata(body: &DnsRecordBody) -> Result<Vec<u8>> {
+    let body = match body.clone() {
+        DnsRecordBody::CNAME { name } => DnsRecordBody::CNAME { name: name.to_lowercase() },
+        DnsRecordBody::NS { name } => DnsRecordBody::NS { name: name.to_lowercase() },
+        DnsRecordBody::PTR { name } => DnsRecordBody::PTR { name: name.to_lowercase() },
+        DnsRecordBody::MX { priority, name } => DnsRecordBody::MX { priority, name: name.to_lowercase() },
+        DnsRecordBody::SRV { priority, weight, port, target } => {
+            DnsRecordBody::SRV { priority, weight, port, target: target.to_lowercase() }
+        },
+        DnsRecordBody::NAPTR { order, preference, flags, services, regexp, replacement } => DnsRecordBody::NAPTR {
+            order, preference, flags, services, regexp,
+            replacement: replacement.to_lowercase(),
+        },
+        DnsRecordBody::SOA { mname, rname, serial, refresh, retry, expire, minimum } => DnsRecordBody::SOA {
+            mname: mname.to_lowercase(),
+            rname: rname.to_lowercase(),
+            serial, refresh, retry, expire, minimum,
+        },
+        body => body,
+    };
+    let mut buf = canonical_buffer();
+    body.write(&mut buf)?;
+    Ok(buf.as_bytes().to_vec())
+}
+
+fn canonical_buffer() -> DnsBuffer {
+    let mut buf = DnsBuffer::with_capacity(TCP_MAX_SIZE);
+    buf.compress = false;
+    buf
+}
+
+fn canonical_name(name: &DnsName) -> Result<Vec<u8>> {
+    let mut buf = canonical_buffer();
+    name.to_lowercase().write(&mut buf)?;
+    Ok(buf.as_bytes().to_vec())
+}
+
+fn supported_algorithm(algorithm: u8) -> bool {
+    [RSASHA256, ECDSAP256SHA256, ED25519].contains(&algorithm)
+}
+
+fn ds_digest_type(digest_type: u8) -> Option<&'static digest::Algorithm> {
+    match digest_type {
+        SHA1 => Some(&digest::SHA1_FOR_LEGACY_USE_ONLY),
+        SHA256 => Some(&digest::SHA256),
+        SHA384 => Some(&digest::SHA384),
+        _ => None,
+    }
+}
+
+/// Checks whether a DS record refers to `key`, the DNSKEY of zone `owner`.
+/// The digest covers the owner name and the key's RDATA, see section 5.1.4
+/// of https://tools.ietf.org/html/rfc4034
+fn ds_matches(owner: &DnsName, key: &DnsRecordBody, ds: &DnsRecordBody) -> bool {
+    let (ds_key_tag, ds_algorithm, digest_type, ds_digest) = match ds {
+        DnsRecordBody::DS { key_tag, algorithm, digest_type, digest } => (*key_tag, *algorithm, *digest_type, digest),
+        _ => return false,
+    };
+    let key_algorithm = match key {
+        DnsRecordBody::DNSKEY { algorithm, .. } => *algorithm,
+        _ => return false,
+    };
+    if key.key_tag() != Some(ds_key_tag) || key_algorithm != ds_algorithm {
+        return false;
+    }
+
+    let algorithm = match ds_digest_type(digest_type) {
+        Some(algorithm) => algorithm,
+        None => return false,
+    };
+    let mut data = match canonical_name(owner) {
+        Ok(data) => data,
+        Err(_) => return false,
+    };
+    match canonical_rdata(key) {
+        Ok(rdata) => data.extend_from_slice(&rdata),
+        Err(_) => return false,
+    }
+    digest::digest(algorithm, &data).as_ref() == &ds_digest[..]
+}
+
+fn verify_signature(algorithm: u8, public_key: &[u8], data: &[u8], sig: &[u8]) -> bool {
+    match algorithm {
+        RSASHA256 => {
+            // The exponent length is one byte, or zero followed by two bytes
+            // for longer exponents.  See section 2 of https://tools.ietf.org/html/rfc3110
+            let (exponent_len, offset) = match public_key {
+                [0, high, low, ..] => (((*high as usize) << 8) | *low as usize, 3),
+                [len, ..] => (*len as usize, 1),
+                [] => return false,
+            };
+            if public_key.len() <= offset + exponent_len {
+                return false;
+            }
+            let components = signature::RsaPublicKeyComponents {
+                n: &public_key[offset + exponent_len..],
+                e: &public_key[offset..offset + exponent_len],
+            };
+            components.verify(&signature::RSA_PKCS1_1024_8192_SHA256_FOR_LEGACY_USE_ONLY, data, sig).is_ok()
+        },
+        ECDSAP256SHA256 => {
+            // DNSKEYs hold the bare point, without the uncompressed marker
+            let mut point = vec![0x04];
+            point.extend_from_slice(public_key);
+            signature::UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_FIXED, point).verify(data, sig).is_ok()
+        },
+        ED25519 => signature::UnparsedPublicKey::new(&signature::ED25519, public_key).verify(data, sig).is_ok(),
+        _ => false,
+    }
+}
+
+/// Hashes a name as in section 5 of https://tools.ietf.org/html/rfc5155,
+/// returning the base32hex form used in NSEC3 owner names.
+fn nsec3_hash(name: &DnsName, salt: &[u8], iterations: u16) -> Result<String> {
+    let mut hash = canonical_name(name)?;
+    for _ in 0..=iterations {
+        let mut context = digest::Context::new(&digest::SHA1_FOR_LEGACY_USE_ONLY);
+        context.update(&hash);
+        context.update(salt);
+        hash = context.finish().as_ref().to_vec();
+    }
+    Ok(base32hex_encode(&hash))
+}
+
+/// Signature validity uses serial number arithmetic, so timestamps keep
+/// working after they wrap in 2106.
+fn in_validity_period(inception: u32, expiration: u32, now: u32) -> bool {
+    (now.wrapping_sub(inception) as i32) >= 0 && (expiration.wrapping_sub(now) as i32) >= 0
+}
+
+/// Groups records into RRsets, sharing an owner name and type.  RRSIGs are
+/// left out, as they are looked up alongside the RRset they cover.
+fn rrsets(records: &[DnsRecord]) -> Vec<Vec<&DnsRecord>> {
+    let mut sets: Vec<Vec<&DnsRecord>> = Vec::new();
+    for record in records.iter().filter(|record| record.preamble.record_type != RecordType::RRSIG) {
+        let existing = sets.iter_mut().find(|set| {
+            set[0].preamble.record_type == record.preamble.record_type && set[0].preamble.name == record.preamble.name
+        });
+        match existing {
+            Some(set) => set.push(record),
+            None => sets.push(vec![record]),
+        }
+    }
+    sets
+}
+
+fn find_record<'a>(records: &'a [DnsRecord], name: &DnsName, record_type: RecordType) -> Option<&'a DnsRecordBody> {
+    records.iter()
+        .find(|record| record.preamble.record_type == record_type && record.preamble.name == *name)
+        .map(|record| &record.body)
+}
+
+fn label_count(name: &DnsName) -> usize {
+    // Wildcard labels aren't counted, see section 3.1.3 of RFC 4034
+    name.labels().filter(|label| *label != b"*").count()
+}
+
+/// Every ancestor of `name`, from the top level domain down to `name` itself.
+fn ancestors(name: &DnsName) -> Vec<DnsName> {
+    let mut names = Vec::new();
+    let mut current = name.clone();
+    while !current.is_root() {
+        let parent = current.parent().unwrap_or_else(DnsName::root);
+        names.push(current);
+        current = parent;
+    }
+    names.reverse();
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::error::DnsError;
+    use crate::packet::DnsQuestion;
+
+    // The fixtures are signed by a made up root, with an RSA/SHA-256 root key,
+    // an ECDSA P-256 key for com and an Ed25519 key for example.com.  All the
+    // signatures are valid for 30 days from this time.
+    const NOW: u32 = 1700086400;
+
+    fn anchors() -> Vec<DnsRecordBody> {
+        vec![DnsRecordBody::DS {
+            key_tag: 13877,
+            algorithm: RSASHA256,
+            digest_type: SHA256,
+            digest: hex_decode("8FEEC169ED84402543793C231CC35A0293D2BCC3A30D7780602FD0A63921ADD3").unwrap(),
+        }]
+    }
+
+    fn load(name: &str, record_type: RecordType) -> Result<DnsPacket> {
+        let name = if name == "." { "root" } else { name };
+        let path = format!("{}.{}.response.txt", name, record_type.to_string().to_lowercase());
+        let bytes = fs::read(&path).map_err(|_| DnsError::InvalidInput(format!("No fixture {}", path)))?;
+        let mut packet = DnsPacket::new();
+        packet.read(&mut DnsBuffer::from_bytes(&bytes))?;
+        Ok(packet)
+    }
+
+    fn check(response: &DnsPacket, now: u32) -> Verdict {
+        validate(response, &anchors(), now, load)
+    }
+
+    #[test]
+    fn test_secure() {
+        let response = load("www.example.com", RecordType::A).unwrap();
+        assert_eq!(check(&response, NOW), Verdict::Secure);
+
+        let response = load("example.com", RecordType::DNSKEY).unwrap();
+        assert_eq!(check(&response, NOW), Verdict::Secure);
+
+        let response = load("com", RecordType::DS).unwrap();
+        assert_eq!(check(&response, NOW), Verdict::Secure);
+    }
+
+    #[test]
+    fn test_secure_denial() {
+        let response = load("missing.example.com", RecordType::A).unwrap();
+        assert_eq!(check(&response, NOW), Verdict::Secure);
+
+        // The NSEC for www.example.com shows it has an A record, so this
+        // empty answer can't be right
+        let mut response = load("www.example.com", RecordType::A).unwrap();
+        let denial = load("www.example.com", RecordType::DS).unwrap();
+        response.answers.clear();
+        response.authorities = denial.authorities;
+        assert_eq!(check(&response, NOW), Verdict::Bogus);
+    }
+
+    #[test]
+    fn test_insecure() {
+        let response = load("www.insecure.com", RecordType::A).unwrap();
+        assert_eq!(check(&response, NOW), Verdict::Insecure);
+    }
+
+    #[test]
+    fn test_stripped_ds() {
+        // Without its DS answer, the parent's genuine NSEC for example.com
+        // still shows a DS record, so the zone can't be taken as unsigned
+        let lookup = |name: &str, record_type: RecordType| {
+            let mut response = load(name, record_type)?;
+            if name == "example.com" && record_type == RecordType::DS {
+                let denial = load("insecure.com", RecordType::DS)?;
+                response.answers.clear();
+                response.authorities = denial.authorities.into_iter()
+                    .filter(|record| record.preamble.name == "com")
+                    .chain(load("example.com", RecordType::NSEC)?.answers)
+                    .collect();
+            }
+            Ok(response)
+        };
+        let response = load("www.example.com", RecordType::A).unwrap();
+        assert_eq!(validate(&response, &anchors(), NOW, lookup), Verdict::Bogus);
+    }
+
+    #[test]
+    fn test_cname_loop() {
+        let mut response = load("www.insecure.com", RecordType::A).unwrap();
+        let cname = |from: &str, to: &str| DnsRecord::cname(from.parse().unwrap(), 3600, to.parse().unwrap());
+        response.questions[0] = DnsQuestion::query("a.insecure.com".parse().unwrap(), RecordType::A);
+        response.answers = vec![cname("a.insecure.com", "a.insecure.com")];
+        assert_eq!(check(&response, NOW), Verdict::Bogus);
+
+        response.answers = vec![cname("a.insecure.com", "b.insecure.com"), cname("b.insecure.com", "a.insecure.com")];
+        assert_eq!(check(&response, NOW), Verdict::Bogus);
+
+        // A long chain that does end is still too long to follow
+        response.answers = (0..20).map(|idx| cname(&format!("{}.insecure.com", idx), &format!("{}.insecure.com", idx + 1))).collect();
+        response.questions[0] = DnsQuestion::query("0.insecure.com".parse().unwrap(), RecordType::A);
+        assert_eq!(check(&response, NOW), Verdict::Bogus);
+    }
+
+    #[test]
+    fn test_bogus() {
+        let mut response = load("www.example.com", RecordType::A).unwrap();
+        response.answers[0].body = DnsRecordBody::A { address: "192.0.2.66".parse().unwrap() };
+        assert_eq!(check(&response, NOW), Verdict::Bogus);
+
+        // Expired signatures, and signatures that aren't valid yet
+        let response = load("www.example.com", RecordType::A).unwrap();
+        assert_eq!(check(&response, NOW + 31 * 86400), Verdict::Bogus);
+        assert_eq!(check(&response, NOW - 2 * 86400), Verdict::Bogus);
+
+        // Stripping the signature from a secure zone
+        let mut response = load("www.example.com", RecordType::A).unwrap();
+        response.answers.retain(|record| record.preamble.record_type != RecordType::RRSIG);
+        assert_eq!(check(&response, NOW), Verdict::Bogus);
+
+        // The genuine root keys don't match the made up root
+        let response = load("www.example.com", RecordType::A).unwrap();
+        assert_eq!(validate(&response, &root_trust_anchors(), NOW, load), Verdict::Bogus);
+    }
+
+    #[test]
+    fn test_indeterminate() {
+        let response = load("www.example.com", RecordType::A).unwrap();
+        assert_eq!(validate(&response, &[], NOW, load), Verdict::Indeterminate);
+
+        let verdict = validate(&response, &anchors(), NOW, |_, _| Err(DnsError::Timeout));
+        assert_eq!(verdict, Verdict::Indeterminate);
+    }
+
+    fn name(name: &str) -> DnsName {
+        name.parse().unwrap()
+    }
+
+    fn prove(denials: &DenialRecords, query: &str, zone: &str) -> Chain<Denial> {
+        denials.prove(&name(query), &Zone { name: name(zone), keys: Vec::new() })
+    }
+
+    #[test]
+    fn test_nsec_proofs() {
+        let (example, www, star, other) = (name("example.com"), name("www.example.com"), name("*.example.com"), name("b.example.com"));
+        let nsec = |owner, next, types| Nsec { owner, next, types };
+
+        // One NSEC covers both the name and the wildcard
+        let denials = DenialRecords { nsecs: vec![nsec(&example, &www, &[RecordType::NS])], nsec3s: Vec::new() };
+        assert_eq!(prove(&denials, "missing.example.com", "example.com"), Ok(Denial::NxDomain));
+        assert_eq!(prove(&denials, "example.com", "example.com"), Ok(Denial::NoData(vec![RecordType::NS])));
+
+        // Nothing rules out *.example.com answering instead
+        let denials = DenialRecords { nsecs: vec![nsec(&other, &www, &[RecordType::A])], nsec3s: Vec::new() };
+        assert_eq!(prove(&denials, "missing.example.com", "example.com"), Err(Verdict::Bogus));
+        assert!(denials.proves_absent(&name("missing.example.com")).unwrap());
+        assert!(!denials.proves_absent(&name("a.example.com")).unwrap());
+
+        // The wildcard exists, without the type asked for
+        let denials = DenialRecords { nsecs: vec![nsec(&star, &www, &[RecordType::TXT])], nsec3s: Vec::new() };
+        assert_eq!(prove(&denials, "missing.example.com", "example.com"), Ok(Denial::NoData(vec![RecordType::TXT])));
+
+        // b.example.com has a child, so exists without any records
+        let child = name("x.b.example.com");
+        let denials = DenialRecords { nsecs: vec![nsec(&example, &child, &[RecordType::NS])], nsec3s: Vec::new() };
+        assert_eq!(prove(&denials, "b.example.com", "example.com"), Ok(Denial::NoData(Vec::new())));
+        assert!(!denials.proves_absent(&name("b.example.com")).unwrap());
+    }
+
+    #[test]
+    fn test_nsec3_proofs() {
+        // The name error example from appendix B.1 of RFC 5155
+        let salt = hex_decode("AABBCCDD").unwrap();
+        let nsec3 = |owner: &str, next: &str, types| Nsec3 {
+            owner_hash: String::from(owner), next_hash: String::from(next), salt: &salt, iterations: 12, opt_out: false, types,
+        };
+        let encloser = || nsec3("B4UM86EGHHDS6NEA196SMVMLO4ORS995", "GJEQE526PLBF1G8MKLP59ENFD789NJGI", &[RecordType::MX, RecordType::RRSIG][..]);
+        let next_closer = || nsec3("0P9MHAVEQVM6T7VBL5LOP2U3T2RP3TOM", "2T7B4G4VSA5SMI47K61MV5BV1A22BOJR", &[RecordType::SOA][..]);
+        let wildcard = || nsec3("35MTHGPGCU1QG68FAB165KLNSNK3DPVL", "B4UM86EGHHDS6NEA196SMVMLO4ORS995", &[RecordType::NS][..]);
+
+        let denials = DenialRecords { nsecs: Vec::new(), nsec3s: vec![encloser(), next_closer(), wildcard()] };
+        assert_eq!(prove(&denials, "a.c.x.w.example", "example"), Ok(Denial::NxDomain));
+        assert_eq!(prove(&denials, "x.w.example", "example"), Ok(Denial::NoData(vec![RecordType::MX, RecordType::RRSIG])));
+        assert!(denials.proves_absent(&name("c.x.w.example")).unwrap());
+        assert!(!denials.proves_absent(&name("x.w.example")).unwrap());
+
+        // Each part of the proof is needed
+        for missing in 0..3 {
+            let mut nsec3s = vec![encloser(), next_closer(), wildcard()];
+            nsec3s.remove(missing);
+            let denials = DenialRecords { nsecs: Vec::new(), nsec3s };
+            assert_eq!(prove(&denials, "a.c.x.w.example", "example"), Err(Verdict::Bogus));
+        }
+
+        // A wildcard without the type, then an opt-out span
+        let wildcard_nodata = nsec3("92PQNEEGTAUE7PJATC3L3QNK738C6V5M", "B4UM86EGHHDS6NEA196SMVMLO4ORS995", &[RecordType::TXT][..]);
+        let denials = DenialRecords { nsecs: Vec::new(), nsec3s: vec![encloser(), next_closer(), wildcard_nodata] };
+        assert_eq!(prove(&denials, "a.c.x.w.example", "example"), Ok(Denial::NoData(vec![RecordType::TXT])));
+
+        let denials = DenialRecords { nsecs: Vec::new(), nsec3s: vec![encloser(), Nsec3 { opt_out: true, ..next_closer() }] };
+        assert_eq!(prove(&denials, "a.c.x.w.example", "example"), Ok(Denial::OptOut));
+
+        // The closest encloser can't be a delegation
+        let delegation = Nsec3 { types: &[RecordType::NS][..], ..encloser() };
+        let denials = DenialRecords { nsecs: Vec::new(), nsec3s: vec![delegation, next_closer(), wildcard()] };
+        assert_eq!(prove(&denials, "a.c.x.w.example", "example"), Err(Verdict::Bogus));
+    }
+
+    #[test]
+    fn test_nsec3_hash() {
+        // Example from appendix A of RFC 5155
+        let salt = hex_decode("AABBCCDD").unwrap();
+        assert_eq!(nsec3_hash(&"example".parse().unwrap(), &salt, 12).unwrap(), "0P9MHAVEQVM6T7VBL5LOP2U3T2RP3TOM");
+        assert_eq!(nsec3_hash(&"a.example".parse().unwrap(), &salt, 12).unwrap(), "35MTHGPGCU1QG68FAB165KLNSNK3DPVL");
+    }
+}
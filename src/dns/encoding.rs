@@ -0,0 +1,156 @@
+use crate::error::{DnsError, Result};
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+const BASE32HEX: &[u8; 32] = b"0123456789ABCDEFGHIJKLMNOPQRSTUV";
+
+pub fn base64_encode(data: &[u8]) -> String {
+    let mut output = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bits = (chunk[0] as u32) << 16 |
+                   (*chunk.get(1).unwrap_or(&0) as u32) << 8 |
+                   (*chunk.get(2).unwrap_or(&0) as u32);
+        for idx in 0..4 {
+            if idx <= chunk.len() {
+                output.push(BASE64[(bits >> (18 - 6 * idx) & 0x3F) as usize] as char);
+            } else {
+                output.push('=');
+            }
+        }
+    }
+    output
+}
+
+/// Decodes base64, ignoring any whitespace, as keys in zone files are often
+/// split across several lines.
+pub fn base64_decode(text: &str) -> Result<Vec<u8>> {
+    let invalid = || DnsError::InvalidInput(String::from("Invalid base64"));
+    let chars: Vec<u8> = text.bytes().filter(|byte| !byte.is_ascii_whitespace()).collect();
+    if !chars.len().is_multiple_of(4) {
+        return Err(invalid());
+    }
+
+    let mut output = Vec::with_capacity(chars.len() / 4 * 3);
+    for (chunk_idx, chunk) in chars.chunks(4).enumerate() {
+        let padding = chunk.iter().rev().take_while(|&&byte| byte == b'=').count();
+        if padding > 2 || (padding > 0 && chunk_idx != chars.len() / 4 - 1) {
+            return Err(invalid());
+        }
+
+        let mut bits = 0_u32;
+        for &byte in &chunk[..4 - padding] {
+            let value = BASE64.iter().position(|&c| c == byte).ok_or_else(invalid)?;
+            bits = bits << 6 | value as u32;
+        }
+        bits <<= 6 * padding as u32;
+
+        let bytes = [(bits >> 16) as u8, (bits >> 8) as u8, bits as u8];
+        output.extend_from_slice(&bytes[..3 - padding]);
+    }
+    Ok(output)
+}
+
+/// Base32 with the extended hex alphabet and no padding, as used for NSEC3
+/// hashed owner names.  See section 3.3 of https://tools.ietf.org/html/rfc5155
+pub fn base32hex_encode(data: &[u8]) -> String {
+    let mut output = String::with_capacity((data.len() * 8).div_ceil(5));
+    let mut bits = 0_u16;
+    let mut bit_count = 0;
+    for &byte in data {
+        bits = bits << 8 | byte as u16;
+        bit_count += 8;
+        while bit_count >= 5 {
+            bit_count -= 5;
+            output.push(BASE32HEX[(bits >> bit_count & 0x1F) as usize] as char);
+        }
+        bits &= (1 << bit_count) - 1;
+    }
+    if bit_count > 0 {
+        output.push(BASE32HEX[(bits << (5 - bit_count) & 0x1F) as usize] as char);
+    }
+    output
+}
+
+pub fn hex_encode(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02X}", byte)).collect()
+}
+
+pub fn hex_decode(text: &str) -> Result<Vec<u8>> {
+    let invalid = || DnsError::InvalidInput(String::from("Invalid hex"));
+    let chars: Vec<u8> = text.bytes().filter(|byte| !byte.is_ascii_whitespace()).collect();
+    if !chars.len().is_multiple_of(2) {
+        return Err(invalid());
+    }
+    chars.chunks(2)
+        .map(|pair| {
+            let pair = std::str::from_utf8(pair).map_err(|_| invalid())?;
+            u8::from_str_radix(pair, 16).map_err(|_| invalid())
+        })
+        .collect()
+}
+
+/// Formats a signature timestamp as YYYYMMDDHHmmSS in UTC, as in section 3.2
+/// of https://tools.ietf.org/html/rfc4034
+pub fn format_timestamp(timestamp: u32) -> String {
+    let days = (timestamp / 86400) as i64;
+    let seconds = timestamp % 86400;
+
+    // Convert days since 1970-01-01 into a civil date, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    format!("{:04}{:02}{:02}{:02}{:02}{:02}", year, month, day,
+            seconds / 3600, seconds / 60 % 60, seconds % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_base64() {
+        // Test vectors from section 10 of https://tools.ietf.org/html/rfc4648
+        let vectors = [("", ""), ("f", "Zg=="), ("fo", "Zm8="), ("foo", "Zm9v"),
+                       ("foob", "Zm9vYg=="), ("fooba", "Zm9vYmE="), ("foobar", "Zm9vYmFy")];
+        for (plain, encoded) in vectors.iter() {
+            assert_eq!(base64_encode(plain.as_bytes()), *encoded);
+            assert_eq!(base64_decode(encoded).unwrap(), plain.as_bytes());
+        }
+        assert_eq!(base64_decode("Zm9v\n YmFy").unwrap(), b"foobar");
+        assert!(base64_decode("Zm9").is_err());
+        assert!(base64_decode("Zg==Zm9v").is_err());
+        assert!(base64_decode("Zm9*").is_err());
+    }
+
+    #[test]
+    fn test_base32hex() {
+        let vectors = [("", ""), ("f", "CO"), ("fo", "CPNG"), ("foo", "CPNMU"),
+                       ("foob", "CPNMUOG"), ("fooba", "CPNMUOJ1"), ("foobar", "CPNMUOJ1E8")];
+        for (plain, encoded) in vectors.iter() {
+            assert_eq!(base32hex_encode(plain.as_bytes()), *encoded);
+        }
+    }
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(hex_encode(&[0x00, 0xAB, 0x1F]), "00AB1F");
+        assert_eq!(hex_decode("00ab1F").unwrap(), vec![0x00, 0xAB, 0x1F]);
+        assert!(hex_decode("0").is_err());
+        assert!(hex_decode("zz").is_err());
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_timestamp(0), "19700101000000");
+        assert_eq!(format_timestamp(951782400), "20000229000000");
+        assert_eq!(format_timestamp(1700000000), "20231114221320");
+        assert_eq!(format_timestamp(u32::MAX), "21060207062815");
+    }
+}
@@ -0,0 +1,181 @@
+use std::io;
+
+/// The part of a DNS packet that was being parsed when an error occurred.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Section {
+    Header,
+    Question,
+    Answer,
+    Authority,
+    Additional,
+}
+
+impl std::fmt::Display for Section {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            Section::Header => { write!(f, "header")?; },
+            Section::Question => { write!(f, "question")?; },
+            Section::Answer => { write!(f, "answer")?; },
+            Section::Authority => { write!(f, "authority")?; },
+            Section::Additional => { write!(f, "additional")?; },
+        }
+        Ok(())
+    }
+}
+
+/// Everything that can go wrong while reading, writing or sending DNS packets.
+#[derive(Debug)]
+pub enum DnsError {
+    // Reading past the end of the received message
+    Truncated { offset: usize },
+    // Writing past the capacity of the buffer
+    BufferFull { offset: usize },
+    // A chain of compression pointers that never reaches the end of a name
+    PointerLoop { offset: usize },
+    // A compression pointer to a later part of the packet
+    ForwardPointer { offset: usize },
+    // A label longer than 63 bytes
+    LabelTooLong { offset: usize },
+    // A name longer than 255 bytes
+    NameTooLong { offset: usize },
+    // A label type other than a length or a pointer, such as the obsolete
+    // extended labels of https://tools.ietf.org/html/rfc6891
+    ReservedLabelType { offset: usize },
+    // Data that doesn't follow the format its record type requires
+    Malformed { offset: usize, message: String },
+    // A bad argument from the caller, such as an unknown record type name
+    InvalidInput(String),
+    // A parse failure, along with the section of the packet it occurred in
+    Section { section: Section, error: Box<DnsError> },
+    // No response arrived before the socket's timeout elapsed
+    Timeout,
+    // Any other socket failure
+    Io(io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, DnsError>;
+
+impl DnsError {
+    /// Attaches the section of the packet being parsed to the error.
+    pub fn in_section(self, section: Section) -> DnsError {
+        match self {
+            DnsError::Section { .. } => self,
+            _ => DnsError::Section { section, error: Box::new(self) },
+        }
+    }
+
+    /// The byte offset in the packet where the error occurred, if known.
+    pub fn offset(&self) -> Option<usize> {
+        match self {
+            DnsError::Truncated { offset } |
+            DnsError::BufferFull { offset } |
+            DnsError::PointerLoop { offset } |
+            DnsError::ForwardPointer { offset } |
+            DnsError::LabelTooLong { offset } |
+            DnsError::NameTooLong { offset } |
+            DnsError::ReservedLabelType { offset } |
+            DnsError::Malformed { offset, .. } => Some(*offset),
+            DnsError::Section { error, .. } => error.offset(),
+            _ => None,
+        }
+    }
+
+    /// The section of the packet being parsed when the error occurred, if known.
+    pub fn section(&self) -> Option<Section> {
+        match self {
+            DnsError::Section { section, .. } => Some(*section),
+            _ => None,
+        }
+    }
+
+    fn kind(&self) -> io::ErrorKind {
+        match self {
+            DnsError::Truncated { .. } => io::ErrorKind::UnexpectedEof,
+            DnsError::BufferFull { .. } |
+            DnsError::InvalidInput(_) => io::ErrorKind::InvalidInput,
+            DnsError::PointerLoop { .. } |
+            DnsError::ForwardPointer { .. } |
+            DnsError::LabelTooLong { .. } |
+            DnsError::NameTooLong { .. } |
+            DnsError::ReservedLabelType { .. } |
+            DnsError::Malformed { .. } => io::ErrorKind::InvalidData,
+            DnsError::Section { error, .. } => error.kind(),
+            DnsError::Timeout => io::ErrorKind::TimedOut,
+            DnsError::Io(err) => err.kind(),
+        }
+    }
+}
+
+impl std::fmt::Display for DnsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DnsError::Truncated { offset } => { write!(f, "Reading beyond the end of the packet at byte {}", offset)?; },
+            DnsError::BufferFull { offset } => { write!(f, "Writing beyond the buffer at byte {}", offset)?; },
+            DnsError::PointerLoop { offset } => { write!(f, "Compression pointer loop at byte {}", offset)?; },
+            DnsError::ForwardPointer { offset } => { write!(f, "Compression pointer leads forwards at byte {}", offset)?; },
+            DnsError::LabelTooLong { offset } => { write!(f, "Label exceeds 63 characters at byte {}", offset)?; },
+            DnsError::NameTooLong { offset } => { write!(f, "Name exceeds 255 bytes at byte {}", offset)?; },
+            DnsError::ReservedLabelType { offset } => { write!(f, "Reserved label type at byte {}", offset)?; },
+            DnsError::Malformed { offset, message } => { write!(f, "{} at byte {}", message, offset)?; },
+            DnsError::InvalidInput(message) => { write!(f, "{}", message)?; },
+            DnsError::Section { section, error } => { write!(f, "{} in the {} section", error, section)?; },
+            DnsError::Timeout => { write!(f, "Timed out waiting for a response")?; },
+            DnsError::Io(err) => { write!(f, "{}", err)?; },
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for DnsError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DnsError::Section { error, .. } => Some(error.as_ref()),
+            DnsError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for DnsError {
+    fn from(err: io::Error) -> DnsError {
+        match err.kind() {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => DnsError::Timeout,
+            _ => DnsError::Io(err),
+        }
+    }
+}
+
+impl From<DnsError> for io::Error {
+    fn from(err: DnsError) -> io::Error {
+        match err {
+            DnsError::Io(err) => err,
+            _ => io::Error::new(err.kind(), err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_section() {
+        let err = DnsError::Truncated { offset: 40 }.in_section(Section::Answer);
+        assert_eq!(err.offset(), Some(40));
+        assert_eq!(err.section(), Some(Section::Answer));
+
+        // The innermost section wins
+        let err = err.in_section(Section::Additional);
+        assert_eq!(err.section(), Some(Section::Answer));
+        assert_eq!(err.to_string(), "Reading beyond the end of the packet at byte 40 in the answer section");
+    }
+
+    #[test]
+    fn test_into_io_error() {
+        let err: io::Error = DnsError::PointerLoop { offset: 12 }.into();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        let err: DnsError = io::Error::new(io::ErrorKind::WouldBlock, "timeout").into();
+        assert!(matches!(err, DnsError::Timeout));
+    }
+}
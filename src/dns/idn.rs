@@ -0,0 +1,133 @@
+use idna::uts46::{AsciiDenyList, DnsLength, Hyphens, Uts46};
+
+use crate::buffer::{escape_label, split_name, unescape_label};
+use crate::error::{DnsError, Result};
+
+/// Converts a name to the ASCII form sent on the wire, following the UTS #46
+/// processing of https://www.unicode.org/reports/tr46/ and the IDNA2008 rules
+/// of https://tools.ietf.org/html/rfc5891.  Unicode labels are mapped, so
+/// Bücher.DE becomes bücher.de, and then punycode encoded into `xn--`
+/// A-labels.
+///
+/// Names that are already ASCII are returned unchanged, so master file
+/// escapes and service labels such as `_sip._udp` pass straight through.
+/// Otherwise each label is unescaped and converted on its own.  A Unicode
+/// label holding an escaped dot has no A-label form, so is rejected.
+pub fn to_ascii(name: &str) -> Result<String> {
+    if name.is_ascii() {
+        return Ok(String::from(name));
+    }
+    let invalid = || DnsError::InvalidInput(format!("Invalid internationalized name {}", name));
+
+    let mut output = String::with_capacity(name.len());
+    for label in split_name(name) {
+        if !output.is_empty() {
+            output.push('.');
+        }
+        if label.is_ascii() && label.contains('\\') {
+            output.push_str(label);
+            continue;
+        }
+        let unescaped = String::from_utf8(unescape_label(label)?).map_err(|_| invalid())?;
+        if unescaped.contains('.') {
+            return Err(invalid());
+        }
+        let ascii = Uts46::new()
+            .to_ascii(unescaped.as_bytes(), AsciiDenyList::EMPTY, Hyphens::Allow, DnsLength::Verify)
+            .map_err(|_| invalid())?;
+        escape_label(ascii.as_bytes(), &mut output);
+    }
+    if ends_with_separator(name) {
+        output.push('.');
+    }
+    Ok(output)
+}
+
+/// Converts the `xn--` A-labels of a name back to Unicode for display, one
+/// label at a time.  Names without any A-labels, and A-labels that aren't
+/// valid punycode, are returned unchanged.
+pub fn to_unicode(name: &str) -> String {
+    let labels = split_name(name);
+    if !labels.iter().any(|label| is_a_label(label)) {
+        return String::from(name);
+    }
+
+    let mut output = String::with_capacity(name.len());
+    for label in labels {
+        if !output.is_empty() {
+            output.push('.');
+        }
+        match label_to_unicode(label) {
+            Some(unicode) => output.push_str(&unicode),
+            None => output.push_str(label),
+        }
+    }
+    if ends_with_separator(name) {
+        output.push('.');
+    }
+    output
+}
+
+fn is_a_label(label: &str) -> bool {
+    label.len() > 4 && label.is_char_boundary(4) && label[..4].eq_ignore_ascii_case("xn--")
+}
+
+fn label_to_unicode(label: &str) -> Option<String> {
+    if !is_a_label(label) {
+        return None;
+    }
+    let unescaped = String::from_utf8(unescape_label(label).ok()?).ok()?;
+    if unescaped.contains('.') {
+        return None;
+    }
+    let (unicode, result) = Uts46::new().to_unicode(unescaped.as_bytes(), AsciiDenyList::EMPTY, Hyphens::Allow);
+    result.ok()?;
+    Some(unicode.into_owned())
+}
+
+/// Whether a name in master file format ends with a dot that separates
+/// labels, rather than an escaped `\.` ending its last label.
+fn ends_with_separator(name: &str) -> bool {
+    let backslashes = name.strip_suffix('.')
+        .map(|rest| rest.bytes().rev().take_while(|&byte| byte == b'\\').count());
+    backslashes.is_some_and(|count| count % 2 == 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_ascii() {
+        assert_eq!(to_ascii("bücher.de").unwrap(), "xn--bcher-kva.de");
+        assert_eq!(to_ascii("Bücher.DE.").unwrap(), "xn--bcher-kva.de.");
+        assert_eq!(to_ascii("例え.テスト").unwrap(), "xn--r8jz45g.xn--zckzah");
+        // The German sharp s is kept under IDNA2008, rather than becoming ss
+        assert_eq!(to_ascii("straße.de").unwrap(), "xn--strae-oqa.de");
+
+        // ASCII names are left alone, case and escapes included
+        assert_eq!(to_ascii("_sip._udp.Example.com").unwrap(), "_sip._udp.Example.com");
+        assert_eq!(to_ascii("a\\.b.example").unwrap(), "a\\.b.example");
+
+        // Escapes in the ASCII labels of a Unicode name are kept, but a dot
+        // can't be part of a Unicode label
+        assert_eq!(to_ascii("a\\.b.Bücher.de.").unwrap(), "a\\.b.xn--bcher-kva.de.");
+        assert_eq!(to_ascii("Bücher.de\\.").unwrap(), "xn--bcher-kva.de\\.");
+        assert_eq!(to_ascii("Bücher.de\\\\.").unwrap(), "xn--bcher-kva.de\\\\.");
+        assert!(matches!(to_ascii("a\\.ü.de"), Err(DnsError::InvalidInput(_))));
+
+        assert!(to_ascii(&format!("{}ü.de", "a".repeat(70))).is_err());
+    }
+
+    #[test]
+    fn test_to_unicode() {
+        assert_eq!(to_unicode("xn--bcher-kva.de"), "bücher.de");
+        assert_eq!(to_unicode("www.XN--R8JZ45G.xn--zckzah."), "www.例え.テスト.");
+        assert_eq!(to_unicode("www.Example.com"), "www.Example.com");
+        // Not valid punycode, so shown as it is
+        assert_eq!(to_unicode("xn--a-.com"), "xn--a-.com");
+        // Escaped dots stay inside their labels
+        assert_eq!(to_unicode("a\\.xn--bcher-kva.de"), "a\\.xn--bcher-kva.de");
+        assert_eq!(to_unicode("a\\.b.xn--bcher-kva.de\\."), "a\\.b.bücher.de\\.");
+    }
+}
@@ -1,4 +1,18 @@
 pub mod server;
 pub mod client;
 pub mod buffer;
-pub mod packet;
\ No newline at end of file
+pub mod packet;
+pub mod error;
+pub mod reverse;
+pub mod srv;
+mod random;
+pub mod caa;
+pub mod encoding;
+pub mod dnssec;
+pub mod naptr;
+pub mod sshfp;
+pub mod tlsa;
+pub mod view;
+pub mod idn;
+pub mod name;
+pub mod builder;
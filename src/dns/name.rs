@@ -0,0 +1,269 @@
+use std::cmp::Ordering;
+use std::hash::{Hash, Hasher};
+use std::str::FromStr;
+
+use crate::buffer::{escape_label, read_name, split_name, unescape_label, DnsBuffer, MAX_NAME_SIZE};
+use crate::error::{DnsError, Result};
+
+/// The longest label allowed, see section 2.3.4 of https://www.ietf.org/rfc/rfc1035.txt
+pub const MAX_LABEL_SIZE: usize = 63;
+
+/// A fully qualified domain name, stored as its sequence of labels.
+///
+/// Labels keep their case and may hold any bytes, but comparison and hashing
+/// ignore ASCII case, as DNS does.  Names are ordered in the canonical order
+/// of section 6.1 of https://tools.ietf.org/html/rfc4034, label by label from
+/// the right.  Every name is absolute, so "example.com." and "example.com"
+/// parse to the same name, which displays without the trailing dot.  The root
+/// displays as ".".
+#[derive(Clone, Default)]
+pub struct DnsName {
+    // Raw labels from left to right, without the empty root label
+    labels: Vec<Vec<u8>>,
+}
+
+impl DnsName {
+    /// The root name, which has no labels.
+    pub fn root() -> DnsName {
+        DnsName { labels: Vec::new() }
+    }
+
+    /// Builds a name from raw labels, left to right, checking that each label
+    /// and the name as a whole fit on the wire.
+    pub fn from_labels<I, L>(labels: I) -> Result<DnsName>
+        where I: IntoIterator<Item = L>, L: AsRef<[u8]> {
+        let name = DnsName { labels: labels.into_iter().map(|label| label.as_ref().to_vec()).collect() };
+        for label in &name.labels {
+            if label.is_empty() || label.len() > MAX_LABEL_SIZE {
+                return Err(DnsError::InvalidInput(format!("Invalid label length {} in {}", label.len(), name)));
+            }
+        }
+        if name.wire_len() > MAX_NAME_SIZE {
+            return Err(DnsError::InvalidInput(format!("Name exceeds {} bytes: {}", MAX_NAME_SIZE, name)));
+        }
+        Ok(name)
+    }
+
+    /// Reads a name from the buffer, following compression pointers.
+    pub fn read(buf: &mut DnsBuffer) -> Result<DnsName> {
+        let mut labels = Vec::new();
+        let end = read_name(buf.as_bytes(), buf.pos, |label| labels.push(label.to_vec()))?;
+        buf.seek(end)?;
+        Ok(DnsName { labels })
+    }
+
+    /// Writes the name, compressed against names already in the buffer unless
+    /// the buffer has compression turned off.
+    pub fn write(&self, buf: &mut DnsBuffer) -> Result<()> {
+        buf.write_label(&self.to_string())
+    }
+
+    /// Writes the name in full, for RDATA that must not contain pointers.
+    pub fn write_uncompressed(&self, buf: &mut DnsBuffer) -> Result<()> {
+        buf.write_uncompressed_label(&self.to_string())
+    }
+
+    /// The labels from left to right, without escapes.
+    pub fn labels(&self) -> impl DoubleEndedIterator<Item = &[u8]> + ExactSizeIterator {
+        self.labels.iter().map(|label| &label[..])
+    }
+
+    pub fn label_count(&self) -> usize {
+        self.labels.len()
+    }
+
+    pub fn is_root(&self) -> bool {
+        self.labels.is_empty()
+    }
+
+    /// The size of the name on the wire without compression, counting every
+    /// length byte and the final root label.
+    pub fn wire_len(&self) -> usize {
+        self.labels.iter().map(|label| label.len() + 1).sum::<usize>() + 1
+    }
+
+    /// The name with its leftmost label removed, or `None` for the root.
+    pub fn parent(&self) -> Option<DnsName> {
+        if self.is_root() {
+            return None;
+        }
+        Some(DnsName { labels: self.labels[1..].to_vec() })
+    }
+
+    /// The name with `label` added on the left, as in www for example.com.
+    pub fn child(&self, label: &[u8]) -> Result<DnsName> {
+        DnsName::from_labels(std::iter::once(label).chain(self.labels()))
+    }
+
+    /// True if this name is `other` or lies below it.  Every name is a
+    /// subdomain of the root.
+    pub fn is_subdomain_of(&self, other: &DnsName) -> bool {
+        self.labels.len() >= other.labels.len() &&
+            self.labels().rev().zip(other.labels().rev()).all(|(a, b)| a.eq_ignore_ascii_case(b))
+    }
+
+    /// A copy with every label in lowercase, as needed for the canonical form
+    /// of DNSSEC.
+    pub fn to_lowercase(&self) -> DnsName {
+        DnsName { labels: self.labels.iter().map(|label| label.to_ascii_lowercase()).collect() }
+    }
+}
+
+impl FromStr for DnsName {
+    type Err = DnsError;
+
+    /// Parses a name in master file format, where escapes such as `\.` and
+    /// `\032` give labels their unusual bytes.
+    fn from_str(name: &str) -> Result<DnsName> {
+        let labels = split_name(name).into_iter().map(unescape_label).collect::<Result<Vec<Vec<u8>>>>()?;
+        DnsName::from_labels(labels)
+    }
+}
+
+impl PartialEq for DnsName {
+    fn eq(&self, other: &DnsName) -> bool {
+        self.labels.len() == other.labels.len() &&
+            self.labels().zip(other.labels()).all(|(a, b)| a.eq_ignore_ascii_case(b))
+    }
+}
+
+impl Eq for DnsName {}
+
+impl PartialEq<str> for DnsName {
+    fn eq(&self, other: &str) -> bool {
+        other.parse::<DnsName>().is_ok_and(|other| *self == other)
+    }
+}
+
+impl PartialEq<&str> for DnsName {
+    fn eq(&self, other: &&str) -> bool {
+        *self == **other
+    }
+}
+
+impl Hash for DnsName {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        state.write_usize(self.labels.len());
+        for label in &self.labels {
+            state.write_usize(label.len());
+            for byte in label {
+                state.write_u8(byte.to_ascii_lowercase());
+            }
+        }
+    }
+}
+
+impl Ord for DnsName {
+    fn cmp(&self, other: &DnsName) -> Ordering {
+        let lowercase = |label: &[u8]| label.iter().map(|byte| byte.to_ascii_lowercase()).collect::<Vec<u8>>();
+        self.labels().rev().map(lowercase).cmp(other.labels().rev().map(lowercase))
+    }
+}
+
+impl PartialOrd for DnsName {
+    fn partial_cmp(&self, other: &DnsName) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl std::fmt::Display for DnsName {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if self.is_root() {
+            return write!(f, ".");
+        }
+        let mut delimiter = "";
+        for label in &self.labels {
+            let mut escaped = String::new();
+            escape_label(label, &mut escaped);
+            write!(f, "{}{}", delimiter, escaped)?;
+            delimiter = ".";
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for DnsName {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "DnsName({})", self)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::*;
+
+    fn name(name: &str) -> DnsName {
+        name.parse().unwrap()
+    }
+
+    #[test]
+    fn test_equality() {
+        assert_eq!(name("WWW.Example.com"), name("www.example.com."));
+        assert_eq!(name("www.example.com"), "www.EXAMPLE.com");
+        assert_ne!(name("www.example.com"), name("www.example.org"));
+        assert_ne!(name("a\\.b.example"), name("a.b.example"));
+        assert_eq!(name(".").to_string(), ".");
+        assert_eq!(name(""), DnsName::root());
+
+        let set: HashSet<DnsName> = ["example.com", "EXAMPLE.COM.", "Example.Com"].iter().map(|n| name(n)).collect();
+        assert_eq!(set.len(), 1);
+    }
+
+    #[test]
+    fn test_canonical_order() {
+        // Example from section 6.1 of RFC 4034
+        let names: Vec<DnsName> = ["example", "a.example", "yljkjljk.a.example", "Z.a.example",
+                                   "zABC.a.EXAMPLE", "z.example", "\\001.z.example", "*.z.example",
+                                   "\\200.z.example"].iter().map(|n| name(n)).collect();
+        let mut shuffled = names.clone();
+        shuffled.reverse();
+        shuffled.sort();
+        assert_eq!(shuffled, names);
+    }
+
+    #[test]
+    fn test_navigation() {
+        let www = name("www.Example.com");
+        assert_eq!(www.labels().collect::<Vec<_>>(), vec![&b"www"[..], b"Example", b"com"]);
+        assert_eq!(www.label_count(), 3);
+        assert_eq!(www.wire_len(), 17);
+        assert_eq!(DnsName::root().wire_len(), 1);
+
+        let parent = www.parent().unwrap();
+        assert_eq!(parent, "example.com");
+        assert_eq!(parent.child(b"www").unwrap(), www);
+        assert_eq!(name("com").parent().unwrap(), DnsName::root());
+        assert!(DnsName::root().parent().is_none());
+
+        assert!(www.is_subdomain_of(&name("EXAMPLE.com")));
+        assert!(www.is_subdomain_of(&www));
+        assert!(www.is_subdomain_of(&DnsName::root()));
+        assert!(!www.is_subdomain_of(&name("ample.com")));
+        assert!(!parent.is_subdomain_of(&www));
+    }
+
+    #[test]
+    fn test_limits() {
+        assert!(DnsName::from_labels(vec![vec![b'a'; 64]]).is_err());
+        assert!(DnsName::from_labels(vec![Vec::new()]).is_err());
+        assert!(DnsName::from_labels(vec![vec![b'a'; 63]; 4]).is_err());
+        assert!(DnsName::from_labels(vec![vec![b'a'; 62]; 4]).is_ok());
+        assert!("bad\\9.example".parse::<DnsName>().is_err());
+    }
+
+    #[test]
+    fn test_read_write() {
+        let mut buf = DnsBuffer::new();
+        name("www.example.com").write(&mut buf).unwrap();
+        name("mail.example.com").write(&mut buf).unwrap();
+        assert_eq!(buf.pos, 24);
+        name("a\\.b.example.com").write_uncompressed(&mut buf).unwrap();
+
+        buf.seek(0).unwrap();
+        assert_eq!(DnsName::read(&mut buf).unwrap().to_string(), "www.example.com");
+        assert_eq!(DnsName::read(&mut buf).unwrap().to_string(), "mail.example.com");
+        assert_eq!(DnsName::read(&mut buf).unwrap().to_string(), "a\\.b.example.com");
+    }
+}
@@ -0,0 +1,225 @@
+use regex::RegexBuilder;
+
+use crate::error::{DnsError, Result};
+use crate::packet::{DnsRecord, DnsRecordBody};
+
+/// Where a NAPTR record leads, depending on its flags.  See section 4.1 of
+/// https://tools.ietf.org/html/rfc3403
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NaptrTarget {
+    // The "U" flag: the rewritten string is the final URI
+    Uri(String),
+    // The "S" flag: look up SRV records for this name
+    Srv(String),
+    // The "A" flag: look up A and AAAA records for this name
+    Address(String),
+    // No flag: look up NAPTR records for this name and carry on
+    Naptr(String),
+}
+
+/// Sorts NAPTR records into the order they should be tried, by order and
+/// then preference.  Any other records are left out.
+pub fn order_naptr_records(records: &[DnsRecord]) -> Vec<&DnsRecord> {
+    let mut naptrs: Vec<(u16, u16, &DnsRecord)> = records.iter()
+        .filter_map(|record| match &record.body {
+            DnsRecordBody::NAPTR { order, preference, .. } => Some((*order, *preference, record)),
+            _ => None,
+        })
+        .collect();
+    naptrs.sort_by_key(|&(order, preference, _)| (order, preference));
+    naptrs.into_iter().map(|(_, _, record)| record).collect()
+}
+
+/// Applies a NAPTR record to `input`, the original string of the lookup such
+/// as an E.164 number.  Returns `None` when the record's regexp doesn't match,
+/// in which case the next record should be tried, or when its flags are for
+/// some other protocol.
+pub fn apply_naptr(record: &DnsRecord, input: &str) -> Result<Option<NaptrTarget>> {
+    let (flags, regexp, replacement) = match &record.body {
+        DnsRecordBody::NAPTR { flags, regexp, replacement, .. } => (flags, regexp, replacement),
+        _ => return Err(DnsError::InvalidInput(String::from("Not a NAPTR record"))),
+    };
+
+    // Only one of the regexp and replacement fields may be used
+    let result = if regexp.is_empty() {
+        replacement.to_string()
+    } else {
+        let regexp = String::from_utf8(regexp.clone())
+            .map_err(|_| DnsError::InvalidInput(String::from("NAPTR regexp is not valid UTF-8")))?;
+        match naptr_rewrite(&regexp, input)? {
+            Some(result) => result,
+            None => return Ok(None),
+        }
+    };
+
+    let flag = flags.first().map(|flag| flag.to_ascii_uppercase());
+    match flag {
+        Some(b'U') => Ok(Some(NaptrTarget::Uri(result))),
+        Some(b'S') => Ok(Some(NaptrTarget::Srv(result))),
+        Some(b'A') => Ok(Some(NaptrTarget::Address(result))),
+        None => Ok(Some(NaptrTarget::Naptr(result))),
+        _ => Ok(None),
+    }
+}
+
+/// Applies a substitution expression such as `!^\+1(.*)$!sip:\1@example.com!`
+/// to `input`, as in section 3.2 of https://tools.ietf.org/html/rfc3402.  The
+/// first character is the delimiter, and a trailing `i` makes the match case
+/// insensitive.  Like sed, only the matched part of the input is replaced.
+/// Returns `None` when the expression doesn't match.
+pub fn naptr_rewrite(regexp: &str, input: &str) -> Result<Option<String>> {
+    let invalid = |message: &str| DnsError::InvalidInput(format!("Invalid NAPTR regexp {}: {}", regexp, message));
+
+    let delimiter = regexp.chars().next().ok_or_else(|| invalid("empty"))?;
+    if delimiter.is_ascii_digit() || delimiter == '\\' || delimiter == 'i' {
+        return Err(invalid("bad delimiter"));
+    }
+
+    // Split on unescaped delimiters, dropping the backslash from escaped ones
+    let mut parts = vec![String::new()];
+    let mut chars = regexp[delimiter.len_utf8()..].chars();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some(next) if next == delimiter => parts.last_mut().unwrap().push(next),
+                Some(next) => {
+                    parts.last_mut().unwrap().push(c);
+                    parts.last_mut().unwrap().push(next);
+                },
+                None => return Err(invalid("trailing backslash")),
+            }
+        } else if c == delimiter {
+            parts.push(String::new());
+        } else {
+            parts.last_mut().unwrap().push(c);
+        }
+    }
+    if parts.len() != 3 || (!parts[2].is_empty() && parts[2] != "i") {
+        return Err(invalid("expected delim-char ere delim-char repl delim-char flags"));
+    }
+
+    let ere = RegexBuilder::new(&parts[0])
+        .case_insensitive(parts[2] == "i")
+        .build()
+        .map_err(|_| invalid("bad regular expression"))?;
+    let captures = match ere.captures(input) {
+        Some(captures) => captures,
+        None => return Ok(None),
+    };
+
+    // Expand \1 to \9 back references, and unescape anything else
+    let mut output = String::new();
+    let mut repl = parts[1].chars();
+    while let Some(c) = repl.next() {
+        if c != '\\' {
+            output.push(c);
+            continue;
+        }
+        match repl.next() {
+            Some(digit @ '1'..='9') => {
+                let idx = digit.to_digit(10).unwrap() as usize;
+                if idx >= captures.len() {
+                    return Err(invalid("back reference to a missing group"));
+                }
+                output.push_str(captures.get(idx).map_or("", |group| group.as_str()));
+            },
+            Some(other) => output.push(other),
+            None => return Err(invalid("trailing backslash")),
+        }
+    }
+
+    let matched = captures.get(0).unwrap();
+    Ok(Some(format!("{}{}{}", &input[..matched.start()], output, &input[matched.end()..])))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::DnsBuffer;
+    use crate::packet::RecordType;
+
+    fn naptr_record(order: u16, preference: u16, flags: &str, services: &str, regexp: &str, replacement: &str) -> DnsRecord {
+        let mut record = DnsRecord::new();
+        record.preamble.name = "4.3.2.1.5.5.5.0.0.8.1.e164.arpa".parse().unwrap();
+        record.preamble.record_type = RecordType::NAPTR;
+        record.body = DnsRecordBody::NAPTR {
+            order,
+            preference,
+            flags: flags.as_bytes().to_vec(),
+            services: services.as_bytes().to_vec(),
+            regexp: regexp.as_bytes().to_vec(),
+            replacement: replacement.parse().unwrap(),
+        };
+        record
+    }
+
+    #[test]
+    fn test_rewrite() {
+        // ENUM style rewrites of an E.164 number, see https://tools.ietf.org/html/rfc6116
+        assert_eq!(naptr_rewrite("!^.*$!sip:info@example.com!", "+18005551234").unwrap(),
+                   Some(String::from("sip:info@example.com")));
+        assert_eq!(naptr_rewrite("!^\\+1(.*)$!sip:\\1@example.com!", "+18005551234").unwrap(),
+                   Some(String::from("sip:8005551234@example.com")));
+        assert_eq!(naptr_rewrite("/^\\+44(.*)$/tel:0\\1/", "+18005551234").unwrap(), None);
+
+        // Escaped delimiters, case insensitivity and partial matches
+        assert_eq!(naptr_rewrite("!a\\!b!c!", "xa!by").unwrap(), Some(String::from("xcy")));
+        assert_eq!(naptr_rewrite("!HTTP!https!i", "http://example.com").unwrap(), Some(String::from("https://example.com")));
+        assert_eq!(naptr_rewrite("!HTTP!https!", "http://example.com").unwrap(), None);
+
+        assert!(naptr_rewrite("", "x").is_err());
+        assert!(naptr_rewrite("1a1b1", "x").is_err());
+        assert!(naptr_rewrite("!a!b", "x").is_err());
+        assert!(naptr_rewrite("!(a!\\2!", "a").is_err());
+        assert!(naptr_rewrite("!(a)!\\2!", "a").is_err());
+    }
+
+    #[test]
+    fn test_resolution_chain() {
+        let records = vec![
+            naptr_record(100, 20, "u", "E2U+sip", "!^\\+1(.*)$!sip:\\1@example.com!", ""),
+            naptr_record(100, 10, "s", "SIP+D2U", "", "_sip._udp.example.com"),
+            naptr_record(50, 10, "", "", "", "next.example.com."),
+        ];
+        let ordered = order_naptr_records(&records);
+        let targets: Vec<Option<NaptrTarget>> = ordered.iter().map(|record| apply_naptr(record, "+18005551234").unwrap()).collect();
+        assert_eq!(targets, vec![
+            Some(NaptrTarget::Naptr(String::from("next.example.com"))),
+            Some(NaptrTarget::Srv(String::from("_sip._udp.example.com"))),
+            Some(NaptrTarget::Uri(String::from("sip:8005551234@example.com"))),
+        ]);
+
+        let record = naptr_record(100, 10, "U", "E2U+sip", "!^\\+44(.*)$!sip:\\1@example.com!", "");
+        assert_eq!(apply_naptr(&record, "+18005551234").unwrap(), None);
+    }
+
+    #[test]
+    fn test_write_naptr() {
+        let record = naptr_record(100, 10, "u", "E2U+sip", "!^.*$!sip:\"info\"@example.com!", "");
+        let mut buf = DnsBuffer::new();
+        record.write(&mut buf).unwrap();
+        buf.pos = 0;
+
+        let mut new_record = DnsRecord::new();
+        new_record.read(&mut buf).unwrap();
+        assert_eq!(new_record.body, record.body);
+        assert_eq!(new_record.body.to_string(), "100\t10\t\"u\"\t\"E2U+sip\"\t\"!^.*$!sip:\\\"info\\\"@example.com!\"\t.");
+    }
+
+    #[test]
+    fn test_write_uri() {
+        let mut record = DnsRecord::new();
+        record.preamble.name = "_ftp._tcp.example.com".parse().unwrap();
+        record.preamble.record_type = RecordType::URI;
+        record.body = DnsRecordBody::URI { priority: 10, weight: 1, target: b"ftp://ftp1.example.com/public".to_vec() };
+
+        let mut buf = DnsBuffer::new();
+        record.write(&mut buf).unwrap();
+        buf.pos = 0;
+
+        let mut new_record = DnsRecord::new();
+        new_record.read(&mut buf).unwrap();
+        assert_eq!(new_record.body, record.body);
+        assert_eq!(new_record.body.to_string(), "10\t1\t\"ftp://ftp1.example.com/public\"");
+    }
+}
@@ -1,37 +1,96 @@
-use std::io::{Result, Error, ErrorKind, Read};
-use std::fs::File;
-use std::net::{Ipv4Addr, Ipv6Addr};
-
-use crate::buffer::DnsBuffer;
-
+use std::convert::TryFrom;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::buffer::{DnsBuffer, UDP_MAX_SIZE};
+use crate::builder::DnsPacketBuilder;
+use crate::error::{DnsError, Result, Section};
+use crate::encoding::{base32hex_encode, base64_encode, format_timestamp, hex_encode};
+use crate::idn;
+use crate::name::DnsName;
+use crate::reverse;
+
+/// Response codes, including the extended ones whose upper 8 bits are carried
+/// in the EDNS OPT record.  See the registry at
+/// https://www.iana.org/assignments/dns-parameters
 #[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u16)]
 pub enum ResponseCode {
+    // Any unassigned code, keeping its number
+    UNKNOWN(u16) = 0xFFFF,
     NOERROR = 0,
     FORMERR = 1,
     SERVFAIL = 2,
     NXDOMAIN = 3,
     NOTIMP = 4,
+    REFUSED = 5,
+    // Dynamic update failures, see https://tools.ietf.org/html/rfc2136
+    YXDOMAIN = 6,
+    YXRRSET = 7,
+    NXRRSET = 8,
+    NOTAUTH = 9,
+    NOTZONE = 10,
+    DSOTYPENI = 11,
+    // Codes above 15 need the upper bits from the OPT record
+    BADVERS = 16,
+    BADKEY = 17,
+    BADTIME = 18,
+    BADMODE = 19,
+    BADNAME = 20,
+    BADALG = 21,
+    BADTRUNC = 22,
+    BADCOOKIE = 23,
 }
 
 impl ResponseCode {
-    pub fn from_num(num: u8) -> ResponseCode {
+    pub fn from_num(num: u16) -> ResponseCode {
         match num {
             0 => ResponseCode::NOERROR,
             1 => ResponseCode::FORMERR,
             2 => ResponseCode::SERVFAIL,
             3 => ResponseCode::NXDOMAIN,
             4 => ResponseCode::NOTIMP,
-            _ => ResponseCode::NOERROR
+            5 => ResponseCode::REFUSED,
+            6 => ResponseCode::YXDOMAIN,
+            7 => ResponseCode::YXRRSET,
+            8 => ResponseCode::NXRRSET,
+            9 => ResponseCode::NOTAUTH,
+            10 => ResponseCode::NOTZONE,
+            11 => ResponseCode::DSOTYPENI,
+            16 => ResponseCode::BADVERS,
+            17 => ResponseCode::BADKEY,
+            18 => ResponseCode::BADTIME,
+            19 => ResponseCode::BADMODE,
+            20 => ResponseCode::BADNAME,
+            21 => ResponseCode::BADALG,
+            22 => ResponseCode::BADTRUNC,
+            23 => ResponseCode::BADCOOKIE,
+            _ => ResponseCode::UNKNOWN(num)
         }
     }
 
-    pub fn to_num(&self) -> u8 {
-        match self {
+    pub fn to_num(&self) -> u16 {
+        match *self {
             ResponseCode::NOERROR => 0,
             ResponseCode::FORMERR => 1,
             ResponseCode::SERVFAIL => 2,
             ResponseCode::NXDOMAIN => 3,
-            ResponseCode::NOTIMP => 4
+            ResponseCode::NOTIMP => 4,
+            ResponseCode::REFUSED => 5,
+            ResponseCode::YXDOMAIN => 6,
+            ResponseCode::YXRRSET => 7,
+            ResponseCode::NXRRSET => 8,
+            ResponseCode::NOTAUTH => 9,
+            ResponseCode::NOTZONE => 10,
+            ResponseCode::DSOTYPENI => 11,
+            ResponseCode::BADVERS => 16,
+            ResponseCode::BADKEY => 17,
+            ResponseCode::BADTIME => 18,
+            ResponseCode::BADMODE => 19,
+            ResponseCode::BADNAME => 20,
+            ResponseCode::BADALG => 21,
+            ResponseCode::BADTRUNC => 22,
+            ResponseCode::BADCOOKIE => 23,
+            ResponseCode::UNKNOWN(num) => num
         }
     }
 }
@@ -44,19 +103,94 @@ impl std::fmt::Display for ResponseCode {
             ResponseCode::SERVFAIL => { write!(f, "SERVFAIL")?; },
             ResponseCode::NXDOMAIN => { write!(f, "NXDOMAIN")?; },
             ResponseCode::NOTIMP => { write!(f, "NOTIMP")?; },
-            _ => { write!(f, "UNKNOWN")?; },
+            ResponseCode::REFUSED => { write!(f, "REFUSED")?; },
+            ResponseCode::YXDOMAIN => { write!(f, "YXDOMAIN")?; },
+            ResponseCode::YXRRSET => { write!(f, "YXRRSET")?; },
+            ResponseCode::NXRRSET => { write!(f, "NXRRSET")?; },
+            ResponseCode::NOTAUTH => { write!(f, "NOTAUTH")?; },
+            ResponseCode::NOTZONE => { write!(f, "NOTZONE")?; },
+            ResponseCode::DSOTYPENI => { write!(f, "DSOTYPENI")?; },
+            ResponseCode::BADVERS => { write!(f, "BADVERS")?; },
+            ResponseCode::BADKEY => { write!(f, "BADKEY")?; },
+            ResponseCode::BADTIME => { write!(f, "BADTIME")?; },
+            ResponseCode::BADMODE => { write!(f, "BADMODE")?; },
+            ResponseCode::BADNAME => { write!(f, "BADNAME")?; },
+            ResponseCode::BADALG => { write!(f, "BADALG")?; },
+            ResponseCode::BADTRUNC => { write!(f, "BADTRUNC")?; },
+            ResponseCode::BADCOOKIE => { write!(f, "BADCOOKIE")?; },
+            ResponseCode::UNKNOWN(num) => { write!(f, "RCODE{}", num)?; },
+        }
+        Ok(())
+    }
+}
+
+/// The kind of message, from the 4 bit OPCODE field of the header.  See the
+/// registry at https://www.iana.org/assignments/dns-parameters
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Opcode {
+    // Any unassigned opcode, keeping its number
+    UNKNOWN(u8) = 0xFF,
+    QUERY = 0,
+    // Inverse query, long obsolete, see https://tools.ietf.org/html/rfc3425
+    IQUERY = 1,
+    STATUS = 2,
+    // Zone change announcement, see https://tools.ietf.org/html/rfc1996
+    NOTIFY = 4,
+    // Dynamic update, see https://tools.ietf.org/html/rfc2136
+    UPDATE = 5,
+    // DNS stateful operations, see https://tools.ietf.org/html/rfc8490
+    DSO = 6,
+}
+
+impl Opcode {
+    pub fn from_num(num: u8) -> Opcode {
+        match num {
+            0 => Opcode::QUERY,
+            1 => Opcode::IQUERY,
+            2 => Opcode::STATUS,
+            4 => Opcode::NOTIFY,
+            5 => Opcode::UPDATE,
+            6 => Opcode::DSO,
+            _ => Opcode::UNKNOWN(num)
+        }
+    }
+
+    pub fn to_num(&self) -> u8 {
+        match *self {
+            Opcode::QUERY => 0,
+            Opcode::IQUERY => 1,
+            Opcode::STATUS => 2,
+            Opcode::NOTIFY => 4,
+            Opcode::UPDATE => 5,
+            Opcode::DSO => 6,
+            Opcode::UNKNOWN(num) => num
+        }
+    }
+}
+
+impl std::fmt::Display for Opcode {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            Opcode::QUERY => { write!(f, "QUERY")?; },
+            Opcode::IQUERY => { write!(f, "IQUERY")?; },
+            Opcode::STATUS => { write!(f, "STATUS")?; },
+            Opcode::NOTIFY => { write!(f, "NOTIFY")?; },
+            Opcode::UPDATE => { write!(f, "UPDATE")?; },
+            Opcode::DSO => { write!(f, "DSO")?; },
+            Opcode::UNKNOWN(num) => { write!(f, "OPCODE{}", num)?; },
         }
         Ok(())
     }
 }
 
-#[derive(Copy, Clone, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct DnsHeader {
     pub id: u16,
     // false if it is a query, true if it is a response
     pub query_response: bool,
-    // Usually always 0, can ignore
-    pub opcode: u8, // 4 bits
+    // Almost always QUERY
+    pub opcode: Opcode, // 4 bits
     // Set to true if the responding server has the canonical record for the query
     pub authoritative_answer: bool,
     // true if the message exceeds 512 bytes and therefore needs to be reissued via TCP
@@ -82,7 +216,7 @@ impl DnsHeader {
         DnsHeader {
             id: 0,
             query_response: false,
-            opcode: 0,
+            opcode: Opcode::QUERY,
             authoritative_answer: false,
             truncated_message: false,
             recursion_desired: false,
@@ -103,7 +237,7 @@ impl DnsHeader {
         // https://www.ietf.org/rfc/rfc1035.txt for details.
         let cur = buf.read()?;
         self.query_response = cur & 0x80 == 0x80;
-        self.opcode = (cur & 0x78) >> 3;
+        self.opcode = Opcode::from_num((cur & 0x78) >> 3);
         self.authoritative_answer = cur & 0x04 == 0x4;
         self.truncated_message = cur & 0x02 == 0x02;
         self.recursion_desired = cur & 0x01 == 0x01;
@@ -111,7 +245,8 @@ impl DnsHeader {
         let cur = buf.read()?;
         self.recursion_available = cur & 0x80 == 0x80;
         self.z = (cur & 0x70) >> 4;
-        self.response_code = ResponseCode::from_num(cur & 0x0F);
+        // Only the lower 4 bits, the rest are added from the OPT record
+        self.response_code = ResponseCode::from_num((cur & 0x0F) as u16);
 
         self.question_count = buf.read_u16()?;
         self.answer_count = buf.read_u16()?;
@@ -122,13 +257,18 @@ impl DnsHeader {
     }
 
     pub fn write(&self, buf: &mut DnsBuffer) -> Result<()> {
+        // The opcode field is four bits wide, so masking a larger one would
+        // send a different opcode
+        if self.opcode.to_num() > 0x0F {
+            return Err(DnsError::InvalidInput(format!("Opcode {} is out of range", self.opcode)));
+        }
         buf.write_u16(self.id)?;
 
-        let mut byte = 0 as u8;
+        let mut byte = 0_u8;
         if self.query_response {
             byte |= 0x80;
         }
-        byte |= self.opcode << 3;
+        byte |= (self.opcode.to_num() & 0x0F) << 3;
         if self.authoritative_answer {
             byte |= 0x04;
         }
@@ -140,12 +280,12 @@ impl DnsHeader {
         }
         buf.write(byte)?;
 
-        byte = 0 as u8;
+        byte = 0_u8;
         if self.recursion_available {
             byte |= 0x80;
         }
         byte |= self.z << 4;
-        byte |= self.response_code.to_num();
+        byte |= (self.response_code.to_num() & 0x0F) as u8;
         buf.write(byte)?;
 
         buf.write_u16(self.question_count)?;
@@ -157,6 +297,12 @@ impl DnsHeader {
     }
 }
 
+impl Default for DnsHeader {
+    fn default() -> Self {
+        DnsHeader::new()
+    }
+}
+
 impl std::fmt::Display for DnsHeader {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         write!(f, "id={} qr={} opcode={} aa={} tc={} rd={} ra={} z={} rcode={}
@@ -168,14 +314,37 @@ QUERY={} ANSWER={} AUTHORITY={} ADDITIONAL={}", self.id, self.query_response, se
     }
 }
 
-#[derive(Debug, Clone)]
+/// Record types, keyed by their IANA assigned number.  Types that aren't
+/// understood keep their number in `UNKNOWN` so they can be written back out.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[repr(u16)]
 pub enum RecordType {
-    UNKNOWN = 0,
+    UNKNOWN(u16) = 0,
     A = 1,
     NS = 2,
     CNAME = 5,
+    SOA = 6,
+    PTR = 12,
     MX = 15,
+    TXT = 16,
     AAAA = 28,
+    SRV = 33,
+    NAPTR = 35,
+    OPT = 41,
+    DS = 43,
+    SSHFP = 44,
+    RRSIG = 46,
+    NSEC = 47,
+    DNSKEY = 48,
+    NSEC3 = 50,
+    NSEC3PARAM = 51,
+    TLSA = 52,
+    SMIMEA = 53,
+    OPENPGPKEY = 61,
+    SVCB = 64,
+    HTTPS = 65,
+    URI = 256,
+    CAA = 257,
 }
 
 impl RecordType {
@@ -184,21 +353,37 @@ impl RecordType {
             1 => RecordType::A,
             2 => RecordType::NS,
             5 => RecordType::CNAME,
+            6 => RecordType::SOA,
+            12 => RecordType::PTR,
             15 => RecordType::MX,
+            16 => RecordType::TXT,
             28 => RecordType::AAAA,
-            _ => RecordType::UNKNOWN,
+            33 => RecordType::SRV,
+            35 => RecordType::NAPTR,
+            41 => RecordType::OPT,
+            43 => RecordType::DS,
+            44 => RecordType::SSHFP,
+            46 => RecordType::RRSIG,
+            47 => RecordType::NSEC,
+            48 => RecordType::DNSKEY,
+            50 => RecordType::NSEC3,
+            51 => RecordType::NSEC3PARAM,
+            52 => RecordType::TLSA,
+            53 => RecordType::SMIMEA,
+            61 => RecordType::OPENPGPKEY,
+            64 => RecordType::SVCB,
+            65 => RecordType::HTTPS,
+            256 => RecordType::URI,
+            257 => RecordType::CAA,
+            _ => RecordType::UNKNOWN(num),
         }
     }
 
+    /// Looks up a record type by name, returning `UNKNOWN(0)` for names it
+    /// doesn't recognise.  Use `str::parse` to get an error instead.
+    #[allow(clippy::should_implement_trait, clippy::ptr_arg)]
     pub fn from_str(rec_type: &String) -> RecordType {
-        match &rec_type[..] {
-            "A" => RecordType::A,
-            "NS" => RecordType::NS,
-            "CNAME" => RecordType::CNAME,
-            "MX" => RecordType::MX,
-            "AAAA" => RecordType::AAAA,
-            _ => RecordType::UNKNOWN,
-        }
+        rec_type.parse().unwrap_or(RecordType::UNKNOWN(0))
     }
 
     pub fn to_num(&self) -> u16 {
@@ -206,44 +391,74 @@ impl RecordType {
             RecordType::A => 1,
             RecordType::NS => 2,
             RecordType::CNAME => 5,
+            RecordType::SOA => 6,
+            RecordType::PTR => 12,
             RecordType::MX => 15,
+            RecordType::TXT => 16,
             RecordType::AAAA => 28,
-            _ => 0,
+            RecordType::SRV => 33,
+            RecordType::NAPTR => 35,
+            RecordType::OPT => 41,
+            RecordType::DS => 43,
+            RecordType::SSHFP => 44,
+            RecordType::RRSIG => 46,
+            RecordType::NSEC => 47,
+            RecordType::DNSKEY => 48,
+            RecordType::NSEC3 => 50,
+            RecordType::NSEC3PARAM => 51,
+            RecordType::TLSA => 52,
+            RecordType::SMIMEA => 53,
+            RecordType::OPENPGPKEY => 61,
+            RecordType::SVCB => 64,
+            RecordType::HTTPS => 65,
+            RecordType::URI => 256,
+            RecordType::CAA => 257,
+            RecordType::UNKNOWN(num) => num,
         }
     }
 }
 
-impl PartialEq for RecordType {
-    fn eq(&self, other: &Self) -> bool {
-        match *self {
-            RecordType::A => {
-                if let RecordType::A = *other { true }
-                else { false }
-            },
-            RecordType::NS => {
-                if let RecordType::NS = *other { true }
-                else { false }
-            },
-            RecordType::CNAME => {
-                if let RecordType::CNAME = *other { true }
-                else { false }
-            },
-            RecordType::MX => {
-                if let RecordType::MX = *other { true }
-                else { false }
-            },
-            RecordType::AAAA => {
-                if let RecordType::AAAA = *other { true }
-                else { false }
+
+impl std::str::FromStr for RecordType {
+    type Err = DnsError;
+
+    fn from_str(rec_type: &str) -> Result<RecordType> {
+        match rec_type {
+            "A" => Ok(RecordType::A),
+            "NS" => Ok(RecordType::NS),
+            "CNAME" => Ok(RecordType::CNAME),
+            "SOA" => Ok(RecordType::SOA),
+            "PTR" => Ok(RecordType::PTR),
+            "MX" => Ok(RecordType::MX),
+            "TXT" => Ok(RecordType::TXT),
+            "AAAA" => Ok(RecordType::AAAA),
+            "SRV" => Ok(RecordType::SRV),
+            "NAPTR" => Ok(RecordType::NAPTR),
+            "OPT" => Ok(RecordType::OPT),
+            "DS" => Ok(RecordType::DS),
+            "SSHFP" => Ok(RecordType::SSHFP),
+            "RRSIG" => Ok(RecordType::RRSIG),
+            "NSEC" => Ok(RecordType::NSEC),
+            "DNSKEY" => Ok(RecordType::DNSKEY),
+            "NSEC3" => Ok(RecordType::NSEC3),
+            "NSEC3PARAM" => Ok(RecordType::NSEC3PARAM),
+            "TLSA" => Ok(RecordType::TLSA),
+            "SMIMEA" => Ok(RecordType::SMIMEA),
+            "OPENPGPKEY" => Ok(RecordType::OPENPGPKEY),
+            "SVCB" => Ok(RecordType::SVCB),
+            "HTTPS" => Ok(RecordType::HTTPS),
+            "URI" => Ok(RecordType::URI),
+            "CAA" => Ok(RecordType::CAA),
+            // The generic TYPE1234 syntax from https://tools.ietf.org/html/rfc3597
+            _ if rec_type.starts_with("TYPE") => {
+                rec_type[4..].parse::<u16>()
+                    .map(RecordType::from_num)
+                    .map_err(|_| DnsError::InvalidInput(format!("Unsupported record type: {}", rec_type)))
             },
-            RecordType::UNKNOWN => {
-                if let RecordType::UNKNOWN = *other { true }
-                else { false }
-            }
+            _ => Err(DnsError::InvalidInput(format!("Unsupported record type: {}", rec_type))),
         }
     }
 }
-impl Eq for RecordType {}
 
 impl std::fmt::Display for RecordType {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
@@ -251,91 +466,149 @@ impl std::fmt::Display for RecordType {
             RecordType::A => { write!(f, "A")?; },
             RecordType::NS => { write!(f, "NS")?; },
             RecordType::CNAME => { write!(f, "CNAME")?; },
+            RecordType::SOA => { write!(f, "SOA")?; },
+            RecordType::PTR => { write!(f, "PTR")?; },
             RecordType::MX => { write!(f, "MX")?; },
+            RecordType::TXT => { write!(f, "TXT")?; },
             RecordType::AAAA => { write!(f, "AAAA")?; },
-            _ => { write!(f, "UNKNOWN")?; },
+            RecordType::SRV => { write!(f, "SRV")?; },
+            RecordType::NAPTR => { write!(f, "NAPTR")?; },
+            RecordType::OPT => { write!(f, "OPT")?; },
+            RecordType::DS => { write!(f, "DS")?; },
+            RecordType::SSHFP => { write!(f, "SSHFP")?; },
+            RecordType::RRSIG => { write!(f, "RRSIG")?; },
+            RecordType::NSEC => { write!(f, "NSEC")?; },
+            RecordType::DNSKEY => { write!(f, "DNSKEY")?; },
+            RecordType::NSEC3 => { write!(f, "NSEC3")?; },
+            RecordType::NSEC3PARAM => { write!(f, "NSEC3PARAM")?; },
+            RecordType::TLSA => { write!(f, "TLSA")?; },
+            RecordType::SMIMEA => { write!(f, "SMIMEA")?; },
+            RecordType::OPENPGPKEY => { write!(f, "OPENPGPKEY")?; },
+            RecordType::SVCB => { write!(f, "SVCB")?; },
+            RecordType::HTTPS => { write!(f, "HTTPS")?; },
+            RecordType::URI => { write!(f, "URI")?; },
+            RecordType::CAA => { write!(f, "CAA")?; },
+            RecordType::UNKNOWN(num) => { write!(f, "TYPE{}", num)?; },
         }
         Ok(())
     }
 }
 
-#[derive(Debug, Clone)]
+/// The class of a record or question.  Almost everything is IN, with CH
+/// used for server metadata like version.bind.  See the registry at
+/// https://www.iana.org/assignments/dns-parameters
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[repr(u16)]
 pub enum RecordClass {
-    UNKNOWN = 0,
+    // Any class we don't know the name of, keeping its number
+    UNKNOWN(u16) = 0,
     IN = 1,
-    MX = 15,
+    CH = 3,
+    HS = 4,
+    // Only used in dynamic updates, see https://tools.ietf.org/html/rfc2136
+    NONE = 254,
+    // Only valid in questions
+    ANY = 255,
 }
 
 impl RecordClass {
     pub fn from_num(num: u16) -> RecordClass {
         match num {
             1 => RecordClass::IN,
-            15 => RecordClass::MX,
-            _ => RecordClass::UNKNOWN,
+            3 => RecordClass::CH,
+            4 => RecordClass::HS,
+            254 => RecordClass::NONE,
+            255 => RecordClass::ANY,
+            _ => RecordClass::UNKNOWN(num),
         }
     }
 
     pub fn to_num(&self) -> u16 {
         match *self {
             RecordClass::IN => 1,
-            RecordClass::MX => 15,
-            _ => 0
+            RecordClass::CH => 3,
+            RecordClass::HS => 4,
+            RecordClass::NONE => 254,
+            RecordClass::ANY => 255,
+            RecordClass::UNKNOWN(num) => num,
         }
     }
 }
 
-impl PartialEq for RecordClass {
-    fn eq(&self, other: &Self) -> bool {
-        match *self {
-            RecordClass::IN => {
-                if let RecordClass::IN = *other { true }
-                else { false }
+impl std::str::FromStr for RecordClass {
+    type Err = DnsError;
+
+    fn from_str(class: &str) -> Result<RecordClass> {
+        match class {
+            "IN" => Ok(RecordClass::IN),
+            "CH" | "CHAOS" => Ok(RecordClass::CH),
+            "HS" => Ok(RecordClass::HS),
+            "NONE" => Ok(RecordClass::NONE),
+            "ANY" => Ok(RecordClass::ANY),
+            // The generic CLASS1234 syntax from https://tools.ietf.org/html/rfc3597
+            _ if class.starts_with("CLASS") => {
+                class[5..].parse::<u16>()
+                    .map(RecordClass::from_num)
+                    .map_err(|_| DnsError::InvalidInput(format!("Unsupported record class: {}", class)))
             },
-            RecordClass::MX => {
-                if let RecordClass::MX = *other { true }
-                else { false }
-            },
-            RecordClass::UNKNOWN => {
-                if let RecordClass::UNKNOWN = *other { true }
-                else { false }
-            }
+            _ => Err(DnsError::InvalidInput(format!("Unsupported record class: {}", class))),
         }
     }
 }
-impl Eq for RecordClass {}
- 
+
 impl std::fmt::Display for RecordClass {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match *self {
             RecordClass::IN => { write!(f, "IN")?; },
-            RecordClass::MX => { write!(f, "MX")?; },
-            _ => { write!(f, "UNKNOWN")?; },
+            RecordClass::CH => { write!(f, "CH")?; },
+            RecordClass::HS => { write!(f, "HS")?; },
+            RecordClass::NONE => { write!(f, "NONE")?; },
+            RecordClass::ANY => { write!(f, "ANY")?; },
+            RecordClass::UNKNOWN(num) => { write!(f, "CLASS{}", num)?; },
         }
         Ok(())
     }
 }
 
-
-#[derive(PartialEq, Eq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub struct DnsQuestion {
-    name: String,
-    record_type: RecordType,
-    record_class: RecordClass,
+    pub(crate) name: DnsName,
+    pub(crate) record_type: RecordType,
+    pub(crate) record_class: RecordClass,
 }
 
 impl DnsQuestion {
     pub fn new() -> DnsQuestion {
         DnsQuestion {
-            name: String::new(),
-            record_type: RecordType::UNKNOWN,
-            record_class: RecordClass::UNKNOWN,
+            name: DnsName::root(),
+            record_type: RecordType::UNKNOWN(0),
+            record_class: RecordClass::UNKNOWN(0),
         }
     }
 
+    /// A question for `record_type` records of `name` in the IN class.
+    pub fn query(name: DnsName, record_type: RecordType) -> DnsQuestion {
+        DnsQuestion::query_class(name, record_type, RecordClass::IN)
+    }
+
+    pub fn query_class(name: DnsName, record_type: RecordType, record_class: RecordClass) -> DnsQuestion {
+        DnsQuestion { name, record_type, record_class }
+    }
+
+    pub fn name(&self) -> &DnsName {
+        &self.name
+    }
+
+    pub fn record_type(&self) -> RecordType {
+        self.record_type
+    }
+
+    pub fn record_class(&self) -> RecordClass {
+        self.record_class
+    }
+
     pub fn read(&mut self, buf: &mut DnsBuffer) -> Result<()> {
-        let mut output_str = String::new();
-        buf.read_label(&mut output_str)?;
-        self.name = output_str;
+        self.name = DnsName::read(buf)?;
         self.record_type = RecordType::from_num(buf.read_u16()?);
         self.record_class = RecordClass::from_num(buf.read_u16()?);
         
@@ -343,43 +616,70 @@ impl DnsQuestion {
     }
 
     pub fn write(&self, buf: &mut DnsBuffer) -> Result<()> {
-        buf.write_label(&self.name[..])?;
+        self.name.write(buf)?;
         buf.write_u16(self.record_type.to_num())?;
         buf.write_u16(self.record_class.to_num())?;
         Ok(())
     }
 }
 
+impl Default for DnsQuestion {
+    fn default() -> Self {
+        DnsQuestion::new()
+    }
+}
+
 impl std::fmt::Display for DnsQuestion {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{}\t{}\t{}", self.name, self.record_type, self.record_class)?;
+        write!(f, "{}\t{}\t{}", display_name(&self.name, f), self.record_type, self.record_class)?;
         Ok(())
     }
 }
 
-#[derive(PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct DnsRecordPreamble {
-    name: String,
-    record_type: RecordType,
-    record_class: RecordClass,
-    ttl: u32,
-    length: u16,
+    pub(crate) name: DnsName,
+    pub(crate) record_type: RecordType,
+    pub(crate) record_class: RecordClass,
+    pub(crate) ttl: u32,
+    pub(crate) length: u16,
 }
 
 impl DnsRecordPreamble {
     pub fn new() -> DnsRecordPreamble {
         DnsRecordPreamble {
-            name: String::new(),
-            record_type: RecordType::UNKNOWN,
-            record_class: RecordClass::UNKNOWN,
+            name: DnsName::root(),
+            record_type: RecordType::UNKNOWN(0),
+            record_class: RecordClass::UNKNOWN(0),
             ttl: 0,
             length: 0
         }
     }
+
+    pub fn name(&self) -> &DnsName {
+        &self.name
+    }
+
+    pub fn record_type(&self) -> RecordType {
+        self.record_type
+    }
+
+    pub fn record_class(&self) -> RecordClass {
+        self.record_class
+    }
+
+    pub fn ttl(&self) -> u32 {
+        self.ttl
+    }
+
+    /// The RDATA length as read off the wire.  Records that were built rather
+    /// than read have a length of zero, since it's only known once written.
+    pub fn length(&self) -> u16 {
+        self.length
+    }
+
     pub fn read(&mut self, buf: &mut DnsBuffer) -> Result<()> {
-        let mut output_str = String::new();
-        buf.read_label(&mut output_str)?;
-        self.name = output_str;
+        self.name = DnsName::read(buf)?;
         self.record_type = RecordType::from_num(buf.read_u16()?);
         self.record_class = RecordClass::from_num(buf.read_u16()?);
         self.ttl = buf.read_u32()?;
@@ -388,41 +688,210 @@ impl DnsRecordPreamble {
     }
 }
 
+impl Default for DnsRecordPreamble {
+    fn default() -> Self {
+        DnsRecordPreamble::new()
+    }
+}
+
 
 impl std::fmt::Display for DnsRecordPreamble {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{}\t{}\t{}\t{}\t{}", self.name, self.record_type, self.record_class, self.ttl, self.length)?;
+        write!(f, "{}\t{}\t{}\t{}\t{}", display_name(&self.name, f), self.record_type, self.record_class, self.ttl, self.length)?;
         Ok(())
     }
 }
 
-#[derive(PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum DnsRecordBody {
+    // Any record type we don't understand, kept as raw RDATA so it can be
+    // written back out unchanged.  See https://tools.ietf.org/html/rfc3597
     UNKNOWN {
-        record_type: u16
+        record_type: u16,
+        data: Vec<u8>
     },
     A {
         address: Ipv4Addr
     },
     CNAME {
-        name: String
+        name: DnsName
     },
     NS {
-        name: String
+        name: DnsName
+    },
+    PTR {
+        name: DnsName
+    },
+    // Start of authority, which also carries the TTL for negative answers
+    SOA {
+        mname: DnsName,
+        rname: DnsName,
+        serial: u32,
+        refresh: u32,
+        retry: u32,
+        expire: u32,
+        minimum: u32
     },
     MX {
         priority: u16,
-        name: String
+        name: DnsName
+    },
+    // One or more character-strings, which aren't necessarily valid UTF-8
+    TXT {
+        strings: Vec<Vec<u8>>
     },
     AAAA {
         address: Ipv6Addr
+    },
+    // Location of a service, see https://tools.ietf.org/html/rfc2782
+    SRV {
+        priority: u16,
+        weight: u16,
+        port: u16,
+        target: DnsName
+    },
+    // Rewrite rule for turning a string into a URI or a name to look up next,
+    // see https://tools.ietf.org/html/rfc3403
+    NAPTR {
+        order: u16,
+        preference: u16,
+        flags: Vec<u8>,
+        services: Vec<u8>,
+        regexp: Vec<u8>,
+        replacement: DnsName
+    },
+    // Digest of a child zone's DNSKEY, see https://tools.ietf.org/html/rfc4034
+    DS {
+        key_tag: u16,
+        algorithm: u8,
+        digest_type: u8,
+        digest: Vec<u8>
+    },
+    // Fingerprint of an SSH host key, see https://tools.ietf.org/html/rfc4255
+    SSHFP {
+        algorithm: u8,
+        fingerprint_type: u8,
+        fingerprint: Vec<u8>
+    },
+    RRSIG {
+        type_covered: RecordType,
+        algorithm: u8,
+        labels: u8,
+        original_ttl: u32,
+        // Seconds since the epoch, modulo 2^32
+        expiration: u32,
+        inception: u32,
+        key_tag: u16,
+        signer_name: DnsName,
+        signature: Vec<u8>
+    },
+    NSEC {
+        next_domain: DnsName,
+        types: Vec<RecordType>
+    },
+    DNSKEY {
+        flags: u16,
+        protocol: u8,
+        algorithm: u8,
+        public_key: Vec<u8>
+    },
+    // Hashed denial of existence, see https://tools.ietf.org/html/rfc5155
+    NSEC3 {
+        hash_algorithm: u8,
+        flags: u8,
+        iterations: u16,
+        salt: Vec<u8>,
+        next_hashed_owner: Vec<u8>,
+        types: Vec<RecordType>
+    },
+    NSEC3PARAM {
+        hash_algorithm: u8,
+        flags: u8,
+        iterations: u16,
+        salt: Vec<u8>
+    },
+    // Certificate association for TLS, see https://tools.ietf.org/html/rfc6698
+    TLSA {
+        usage: u8,
+        selector: u8,
+        matching_type: u8,
+        data: Vec<u8>
+    },
+    // TLSA for S/MIME certificates, see https://tools.ietf.org/html/rfc8162
+    SMIMEA {
+        usage: u8,
+        selector: u8,
+        matching_type: u8,
+        data: Vec<u8>
+    },
+    // An OpenPGP transferable public key, see https://tools.ietf.org/html/rfc7929
+    OPENPGPKEY {
+        public_key: Vec<u8>
+    },
+    // Service binding, with the endpoint and parameters for connecting to a
+    // service.  See https://tools.ietf.org/html/rfc9460
+    SVCB {
+        // Zero for alias mode, where the target is simply another name to query
+        priority: u16,
+        target: DnsName,
+        params: Vec<SvcParam>
+    },
+    // SVCB for HTTP origins, with the same format
+    HTTPS {
+        priority: u16,
+        target: DnsName,
+        params: Vec<SvcParam>
+    },
+    // A URI for a service, see https://tools.ietf.org/html/rfc7553
+    URI {
+        priority: u16,
+        weight: u16,
+        // The rest of the RDATA, rather than a length-prefixed character-string
+        target: Vec<u8>
+    },
+    // Which CAs may issue certificates, see https://tools.ietf.org/html/rfc8659
+    CAA {
+        flags: u8,
+        tag: String,
+        value: Vec<u8>
     }
 }
 
 impl DnsRecordBody {
 
-    /// Reads the DNS record body based on the record type provided by the record preamble.
-    pub fn read(&mut self, record_type: &RecordType, buf: &mut DnsBuffer) -> Result<DnsRecordBody> {
+    /// The type of record this body belongs to.
+    pub fn record_type(&self) -> RecordType {
+        match self {
+            DnsRecordBody::UNKNOWN { record_type, .. } => RecordType::from_num(*record_type),
+            DnsRecordBody::A { .. } => RecordType::A,
+            DnsRecordBody::CNAME { .. } => RecordType::CNAME,
+            DnsRecordBody::NS { .. } => RecordType::NS,
+            DnsRecordBody::PTR { .. } => RecordType::PTR,
+            DnsRecordBody::SOA { .. } => RecordType::SOA,
+            DnsRecordBody::MX { .. } => RecordType::MX,
+            DnsRecordBody::TXT { .. } => RecordType::TXT,
+            DnsRecordBody::AAAA { .. } => RecordType::AAAA,
+            DnsRecordBody::SRV { .. } => RecordType::SRV,
+            DnsRecordBody::NAPTR { .. } => RecordType::NAPTR,
+            DnsRecordBody::DS { .. } => RecordType::DS,
+            DnsRecordBody::SSHFP { .. } => RecordType::SSHFP,
+            DnsRecordBody::RRSIG { .. } => RecordType::RRSIG,
+            DnsRecordBody::NSEC { .. } => RecordType::NSEC,
+            DnsRecordBody::DNSKEY { .. } => RecordType::DNSKEY,
+            DnsRecordBody::NSEC3 { .. } => RecordType::NSEC3,
+            DnsRecordBody::NSEC3PARAM { .. } => RecordType::NSEC3PARAM,
+            DnsRecordBody::TLSA { .. } => RecordType::TLSA,
+            DnsRecordBody::SMIMEA { .. } => RecordType::SMIMEA,
+            DnsRecordBody::OPENPGPKEY { .. } => RecordType::OPENPGPKEY,
+            DnsRecordBody::SVCB { .. } => RecordType::SVCB,
+            DnsRecordBody::HTTPS { .. } => RecordType::HTTPS,
+            DnsRecordBody::URI { .. } => RecordType::URI,
+            DnsRecordBody::CAA { .. } => RecordType::CAA,
+        }
+    }
+
+    /// Reads the DNS record body based on the record type and length provided by the record preamble.
+    pub fn read(&mut self, record_type: &RecordType, length: u16, buf: &mut DnsBuffer) -> Result<DnsRecordBody> {
         match *record_type {
             RecordType::A => {
                 Ok(DnsRecordBody::A {
@@ -430,26 +899,46 @@ impl DnsRecordBody {
                 })
             }
             RecordType::CNAME => {
-                let mut output_str = String::new();
-                buf.read_label(&mut output_str)?;
                 Ok(DnsRecordBody::CNAME {
-                    name: output_str
+                    name: DnsName::read(buf)?
                 })
             }
             RecordType::NS => {
-                let mut output_str = String::new();
-                buf.read_label(&mut output_str)?;
                 Ok(DnsRecordBody::NS {
-                    name: output_str
+                    name: DnsName::read(buf)?
+                })
+            }
+            RecordType::PTR => {
+                Ok(DnsRecordBody::PTR {
+                    name: DnsName::read(buf)?
+                })
+            }
+            RecordType::SOA => {
+                Ok(DnsRecordBody::SOA {
+                    mname: DnsName::read(buf)?,
+                    rname: DnsName::read(buf)?,
+                    serial: buf.read_u32()?,
+                    refresh: buf.read_u32()?,
+                    retry: buf.read_u32()?,
+                    expire: buf.read_u32()?,
+                    minimum: buf.read_u32()?,
                 })
             }
             RecordType::MX => {
                 let priority = buf.read_u16()?;
-                let mut output_str = String::new();
-                buf.read_label(&mut output_str)?;
                 Ok(DnsRecordBody::MX {
-                    name: output_str,
-                    priority: priority,
+                    name: DnsName::read(buf)?,
+                    priority,
+                })
+            }
+            RecordType::TXT => {
+                let end = buf.pos + length as usize;
+                let mut strings = Vec::new();
+                while buf.pos < end {
+                    strings.push(buf.read_character_string()?);
+                }
+                Ok(DnsRecordBody::TXT {
+                    strings
                 })
             }
             RecordType::AAAA => {
@@ -458,162 +947,1157 @@ impl DnsRecordBody {
                 let raw_addr3 = buf.read_u32()?;
                 let raw_addr4 = buf.read_u32()?;
                 let addr = Ipv6Addr::new(((raw_addr1 >> 16) & 0xFFFF) as u16,
-                                         ((raw_addr1 >> 0) & 0xFFFF) as u16,
+                                         (raw_addr1 & 0xFFFF) as u16,
                                          ((raw_addr2 >> 16) & 0xFFFF) as u16,
-                                         ((raw_addr2 >> 0) & 0xFFFF) as u16,
+                                         (raw_addr2 & 0xFFFF) as u16,
                                          ((raw_addr3 >> 16) & 0xFFFF) as u16,
-                                         ((raw_addr3 >> 0) & 0xFFFF) as u16,
+                                         (raw_addr3 & 0xFFFF) as u16,
                                          ((raw_addr4 >> 16) & 0xFFFF) as u16,
-                                         ((raw_addr4 >> 0) & 0xFFFF) as u16);
+                                         (raw_addr4 & 0xFFFF) as u16);
     
                 Ok(DnsRecordBody::AAAA {
                     address: addr,
                 })
             },
+            RecordType::SRV => {
+                let priority = buf.read_u16()?;
+                let weight = buf.read_u16()?;
+                let port = buf.read_u16()?;
+                let target = DnsName::read(buf)?;
+                Ok(DnsRecordBody::SRV {
+                    priority,
+                    weight,
+                    port,
+                    target
+                })
+            },
+            RecordType::NAPTR => {
+                let order = buf.read_u16()?;
+                let preference = buf.read_u16()?;
+                let flags = buf.read_character_string()?;
+                let services = buf.read_character_string()?;
+                let regexp = buf.read_character_string()?;
+                let replacement = DnsName::read(buf)?;
+                Ok(DnsRecordBody::NAPTR {
+                    order,
+                    preference,
+                    flags,
+                    services,
+                    regexp,
+                    replacement
+                })
+            },
+            RecordType::URI => {
+                let end = buf.pos + length as usize;
+                let priority = buf.read_u16()?;
+                let weight = buf.read_u16()?;
+                Ok(DnsRecordBody::URI {
+                    priority,
+                    weight,
+                    target: read_bytes_until(buf, end)?
+                })
+            },
+            RecordType::DS => {
+                let end = buf.pos + length as usize;
+                Ok(DnsRecordBody::DS {
+                    key_tag: buf.read_u16()?,
+                    algorithm: buf.read()?,
+                    digest_type: buf.read()?,
+                    digest: read_bytes_until(buf, end)?
+                })
+            },
+            RecordType::RRSIG => {
+                let end = buf.pos + length as usize;
+                let type_covered = RecordType::from_num(buf.read_u16()?);
+                let algorithm = buf.read()?;
+                let labels = buf.read()?;
+                let original_ttl = buf.read_u32()?;
+                let expiration = buf.read_u32()?;
+                let inception = buf.read_u32()?;
+                let key_tag = buf.read_u16()?;
+                let signer_name = DnsName::read(buf)?;
+                Ok(DnsRecordBody::RRSIG {
+                    type_covered,
+                    algorithm,
+                    labels,
+                    original_ttl,
+                    expiration,
+                    inception,
+                    key_tag,
+                    signer_name,
+                    signature: read_bytes_until(buf, end)?
+                })
+            },
+            RecordType::NSEC => {
+                let end = buf.pos + length as usize;
+                let next_domain = DnsName::read(buf)?;
+                Ok(DnsRecordBody::NSEC {
+                    next_domain,
+                    types: read_type_bitmap(buf, end)?
+                })
+            },
+            RecordType::SSHFP => {
+                let end = buf.pos + length as usize;
+                Ok(DnsRecordBody::SSHFP {
+                    algorithm: buf.read()?,
+                    fingerprint_type: buf.read()?,
+                    fingerprint: read_bytes_until(buf, end)?
+                })
+            },
+            RecordType::TLSA | RecordType::SMIMEA => {
+                let end = buf.pos + length as usize;
+                let usage = buf.read()?;
+                let selector = buf.read()?;
+                let matching_type = buf.read()?;
+                let data = read_bytes_until(buf, end)?;
+                if *record_type == RecordType::TLSA {
+                    Ok(DnsRecordBody::TLSA { usage, selector, matching_type, data })
+                } else {
+                    Ok(DnsRecordBody::SMIMEA { usage, selector, matching_type, data })
+                }
+            },
+            RecordType::OPENPGPKEY => {
+                let end = buf.pos + length as usize;
+                Ok(DnsRecordBody::OPENPGPKEY {
+                    public_key: read_bytes_until(buf, end)?
+                })
+            },
+            RecordType::DNSKEY => {
+                let end = buf.pos + length as usize;
+                Ok(DnsRecordBody::DNSKEY {
+                    flags: buf.read_u16()?,
+                    protocol: buf.read()?,
+                    algorithm: buf.read()?,
+                    public_key: read_bytes_until(buf, end)?
+                })
+            },
+            RecordType::NSEC3 => {
+                let end = buf.pos + length as usize;
+                let hash_algorithm = buf.read()?;
+                let flags = buf.read()?;
+                let iterations = buf.read_u16()?;
+                let salt = buf.read_character_string()?;
+                let next_hashed_owner = buf.read_character_string()?;
+                Ok(DnsRecordBody::NSEC3 {
+                    hash_algorithm,
+                    flags,
+                    iterations,
+                    salt,
+                    next_hashed_owner,
+                    types: read_type_bitmap(buf, end)?
+                })
+            },
+            RecordType::NSEC3PARAM => {
+                Ok(DnsRecordBody::NSEC3PARAM {
+                    hash_algorithm: buf.read()?,
+                    flags: buf.read()?,
+                    iterations: buf.read_u16()?,
+                    salt: buf.read_character_string()?
+                })
+            },
+            RecordType::SVCB | RecordType::HTTPS => {
+                let end = buf.pos + length as usize;
+                let priority = buf.read_u16()?;
+                let target = DnsName::read(buf)?;
+                let params = read_svc_params(buf, end)?;
+                if *record_type == RecordType::SVCB {
+                    Ok(DnsRecordBody::SVCB { priority, target, params })
+                } else {
+                    Ok(DnsRecordBody::HTTPS { priority, target, params })
+                }
+            }
+            RecordType::CAA => {
+                let start = buf.pos;
+                let flags = buf.read()?;
+                // Tags outside the alphanumeric syntax are kept, so the rest of
+                // the packet still reads, and left to `caa_permits` to judge
+                let tag = buf.read_character_string()?;
+                let value_len = (start + length as usize).checked_sub(buf.pos)
+                    .ok_or(DnsError::Malformed { offset: start, message: String::from("CAA tag overruns the record") })?;
+                let value = buf.get_range(buf.pos, value_len)?.to_vec();
+                buf.seek(buf.pos + value_len)?;
+                Ok(DnsRecordBody::CAA {
+                    flags,
+                    tag: String::from_utf8_lossy(&tag).into_owned(),
+                    value
+                })
+            },
             _ => {
+                let data = buf.get_range(buf.pos, length as usize)?.to_vec();
+                buf.seek(buf.pos + length as usize)?;
                 Ok(DnsRecordBody::UNKNOWN {
-                    record_type: record_type.to_num()
+                    record_type: record_type.to_num(),
+                    data
                 })
             }
         }
     }
-}
 
-impl std::fmt::Display for DnsRecordBody {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        match &self {
+    /// Writes the RDATA, without the length that precedes it.
+    pub fn write(&self, buf: &mut DnsBuffer) -> Result<()> {
+        match self {
             DnsRecordBody::A { address } => {
-                let octets = address.octets();
-                write!(f, "{}.{}.{}.{}", octets[0], octets[1], octets[2], octets[3])?;
+                for octet in address.octets().iter() {
+                    buf.write(*octet)?;
+                }
+            },
+            DnsRecordBody::CNAME { name } | DnsRecordBody::NS { name } | DnsRecordBody::PTR { name } => {
+                name.write(buf)?;
             },
-            DnsRecordBody::CNAME { name } | DnsRecordBody::NS { name } => {
-                write!(f, "{}", name)?;
+            DnsRecordBody::SOA { mname, rname, serial, refresh, retry, expire, minimum } => {
+                mname.write(buf)?;
+                rname.write(buf)?;
+                buf.write_u32(*serial)?;
+                buf.write_u32(*refresh)?;
+                buf.write_u32(*retry)?;
+                buf.write_u32(*expire)?;
+                buf.write_u32(*minimum)?;
             },
             DnsRecordBody::MX { priority, name } => {
-                write!(f, "{}\t{}", name, priority)?;
+                buf.write_u16(*priority)?;
+                name.write(buf)?;
+            },
+            DnsRecordBody::TXT { strings } => {
+                // The RDATA holds at least one character-string, even if empty
+                if strings.is_empty() {
+                    buf.write_character_string(&[])?;
+                }
+                for string in strings {
+                    buf.write_character_string(string)?;
+                }
             },
             DnsRecordBody::AAAA { address } => {
-                let segments = address.segments();
-                write!(f, "{}:{}:{}:{}:{}:{}:{}:{}", segments[0], segments[1], segments[2],
-                    segments[3], segments[4], segments[5], segments[6], segments[7])?;
+                for segment in address.segments().iter() {
+                    buf.write_u16(*segment)?;
+                }
+            },
+            DnsRecordBody::SRV { priority, weight, port, target } => {
+                buf.write_u16(*priority)?;
+                buf.write_u16(*weight)?;
+                buf.write_u16(*port)?;
+                // RFC 2782 forbids compressing the target
+                target.write_uncompressed(buf)?;
+            },
+            DnsRecordBody::NAPTR { order, preference, flags, services, regexp, replacement } => {
+                buf.write_u16(*order)?;
+                buf.write_u16(*preference)?;
+                buf.write_character_string(flags)?;
+                buf.write_character_string(services)?;
+                buf.write_character_string(regexp)?;
+                // RFC 3403 forbids compressing the replacement
+                replacement.write_uncompressed(buf)?;
+            },
+            DnsRecordBody::URI { priority, weight, target } => {
+                buf.write_u16(*priority)?;
+                buf.write_u16(*weight)?;
+                buf.write_bytes(target)?;
+            },
+            DnsRecordBody::DS { key_tag, algorithm, digest_type, digest } => {
+                buf.write_u16(*key_tag)?;
+                buf.write(*algorithm)?;
+                buf.write(*digest_type)?;
+                buf.write_bytes(digest)?;
+            },
+            DnsRecordBody::SSHFP { algorithm, fingerprint_type, fingerprint } => {
+                buf.write(*algorithm)?;
+                buf.write(*fingerprint_type)?;
+                buf.write_bytes(fingerprint)?;
+            },
+            DnsRecordBody::TLSA { usage, selector, matching_type, data } | DnsRecordBody::SMIMEA { usage, selector, matching_type, data } => {
+                buf.write(*usage)?;
+                buf.write(*selector)?;
+                buf.write(*matching_type)?;
+                buf.write_bytes(data)?;
+            },
+            DnsRecordBody::OPENPGPKEY { public_key } => {
+                buf.write_bytes(public_key)?;
+            },
+            DnsRecordBody::RRSIG { type_covered, algorithm, labels, original_ttl, expiration, inception, key_tag, signer_name, signature } => {
+                buf.write_u16(type_covered.to_num())?;
+                buf.write(*algorithm)?;
+                buf.write(*labels)?;
+                buf.write_u32(*original_ttl)?;
+                buf.write_u32(*expiration)?;
+                buf.write_u32(*inception)?;
+                buf.write_u16(*key_tag)?;
+                // Names in DNSSEC records are never compressed, see section 6.2 of RFC 4034
+                signer_name.write_uncompressed(buf)?;
+                buf.write_bytes(signature)?;
             },
-            DnsRecordBody::UNKNOWN { record_type } => {
-                write!(f, "UNKNOWN: {}", record_type)?;
+            DnsRecordBody::NSEC { next_domain, types } => {
+                next_domain.write_uncompressed(buf)?;
+                write_type_bitmap(buf, types)?;
+            },
+            DnsRecordBody::DNSKEY { flags, protocol, algorithm, public_key } => {
+                buf.write_u16(*flags)?;
+                buf.write(*protocol)?;
+                buf.write(*algorithm)?;
+                buf.write_bytes(public_key)?;
+            },
+            DnsRecordBody::NSEC3 { hash_algorithm, flags, iterations, salt, next_hashed_owner, types } => {
+                buf.write(*hash_algorithm)?;
+                buf.write(*flags)?;
+                buf.write_u16(*iterations)?;
+                buf.write_character_string(salt)?;
+                buf.write_character_string(next_hashed_owner)?;
+                write_type_bitmap(buf, types)?;
+            },
+            DnsRecordBody::NSEC3PARAM { hash_algorithm, flags, iterations, salt } => {
+                buf.write(*hash_algorithm)?;
+                buf.write(*flags)?;
+                buf.write_u16(*iterations)?;
+                buf.write_character_string(salt)?;
+            },
+            DnsRecordBody::SVCB { priority, target, params } | DnsRecordBody::HTTPS { priority, target, params } => {
+                buf.write_u16(*priority)?;
+                // The target name is never compressed, see section 2.2 of RFC 9460
+                target.write_uncompressed(buf)?;
+                // Params must be sent in increasing key order
+                let mut sorted: Vec<&SvcParam> = params.iter().collect();
+                sorted.sort_by_key(|param| param.key());
+                for param in sorted {
+                    param.write(buf)?;
+                }
+            },
+            DnsRecordBody::CAA { flags, tag, value } => {
+                buf.write(*flags)?;
+                buf.write_character_string(tag.as_bytes())?;
+                buf.write_bytes(value)?;
+            },
+            DnsRecordBody::UNKNOWN { data, .. } => {
+                buf.write_bytes(data)?;
             }
         }
         Ok(())
     }
-}
 
-#[derive(PartialEq, Eq)]
-pub struct DnsRecord {
-    preamble: DnsRecordPreamble,
-    body: DnsRecordBody
-}
+    /// The key tag identifying a DNSKEY, as referenced by RRSIG and DS
+    /// records.  See appendix B of https://tools.ietf.org/html/rfc4034
+    pub fn key_tag(&self) -> Option<u16> {
+        match self {
+            DnsRecordBody::DNSKEY { flags, protocol, algorithm, public_key } => {
+                // RSA/MD5 keys use the low 16 bits of the modulus instead
+                if *algorithm == 1 {
+                    let len = public_key.len();
+                    if len < 3 {
+                        return None;
+                    }
+                    return Some((public_key[len - 3] as u16) << 8 | public_key[len - 2] as u16);
+                }
 
-impl DnsRecord {
-    pub fn new() -> DnsRecord {
-        DnsRecord {
-            preamble: DnsRecordPreamble::new(),
-            body: DnsRecordBody::UNKNOWN {
-                record_type: 0
-            }
+                let mut rdata = vec![(flags >> 8) as u8, (flags & 0xFF) as u8, *protocol, *algorithm];
+                rdata.extend_from_slice(public_key);
+                let mut acc = 0_u32;
+                for (idx, byte) in rdata.iter().enumerate() {
+                    acc += if idx & 1 == 0 { (*byte as u32) << 8 } else { *byte as u32 };
+                }
+                acc += (acc >> 16) & 0xFFFF;
+                Some((acc & 0xFFFF) as u16)
+            },
+            _ => None,
         }
     }
+}
 
-    pub fn read(&mut self, buf: &mut DnsBuffer) -> Result<()> {
-        self.preamble.read(buf)?;
-        self.body = self.body.read(&self.preamble.record_type, buf)?;
-        Ok(())
-    }
-
-    pub fn write(&self, buf: &mut DnsBuffer) -> Result<()> {
-        buf.write_label(&self.preamble.name[..])?;
-        buf.write_u16(self.preamble.record_type.to_num())?;
-        buf.write_u16(self.preamble.record_class.to_num())?;
-        buf.write_u32(self.preamble.ttl)?;
-        let length_pos = buf.pos;
-        buf.write_u16(self.preamble.length)?;
-        
-        match &self.body {
+impl std::fmt::Display for DnsRecordBody {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match &self {
             DnsRecordBody::A { address } => {
-                for octet in address.octets().iter() {
-                    buf.write(*octet)?;
-                }
+                let octets = address.octets();
+                write!(f, "{}.{}.{}.{}", octets[0], octets[1], octets[2], octets[3])?;
             },
-            DnsRecordBody::CNAME { name } | DnsRecordBody::NS { name } => {
-                buf.write_label(&name[..])?;
-                let size = buf.pos - (length_pos + 2);
-                buf.set_u16(length_pos, size as u16)?;
+            DnsRecordBody::CNAME { name } | DnsRecordBody::NS { name } | DnsRecordBody::PTR { name } => {
+                write!(f, "{}", display_name(name, f))?;
+            },
+            DnsRecordBody::SOA { mname, rname, serial, refresh, retry, expire, minimum } => {
+                write!(f, "{}\t{}\t{}\t{}\t{}\t{}\t{}", display_name(mname, f), display_name(rname, f), serial, refresh, retry, expire, minimum)?;
             },
             DnsRecordBody::MX { priority, name } => {
-                buf.write_u16(*priority)?;
-                buf.write_label(&name[..])?;
-                let size = buf.pos - (length_pos + 2);
-                buf.set_u16(length_pos, size as u16)?;
+                write!(f, "{}\t{}", display_name(name, f), priority)?;
+            },
+            DnsRecordBody::TXT { strings } => {
+                let mut delimiter = "";
+                for string in strings {
+                    write!(f, "{}", delimiter)?;
+                    write_quoted(f, string)?;
+                    delimiter = " ";
+                }
             },
             DnsRecordBody::AAAA { address } => {
-                for segment in address.segments().iter() {
-                    buf.write_u16(*segment)?;
+                let segments = address.segments();
+                write!(f, "{}:{}:{}:{}:{}:{}:{}:{}", segments[0], segments[1], segments[2],
+                    segments[3], segments[4], segments[5], segments[6], segments[7])?;
+            },
+            DnsRecordBody::SRV { priority, weight, port, target } => {
+                write!(f, "{}\t{}\t{}\t{}", priority, weight, port, display_name(target, f))?;
+            },
+            DnsRecordBody::NAPTR { order, preference, flags, services, regexp, replacement } => {
+                write!(f, "{}\t{}\t", order, preference)?;
+                write_quoted(f, flags)?;
+                write!(f, "\t")?;
+                write_quoted(f, services)?;
+                write!(f, "\t")?;
+                write_quoted(f, regexp)?;
+                write!(f, "\t{}", display_name(replacement, f))?;
+            },
+            DnsRecordBody::URI { priority, weight, target } => {
+                write!(f, "{}\t{}\t", priority, weight)?;
+                write_quoted(f, target)?;
+            },
+            DnsRecordBody::DS { key_tag, algorithm, digest_type, digest } => {
+                write!(f, "{}\t{}\t{}\t{}", key_tag, algorithm, digest_type, hex_encode(digest))?;
+            },
+            DnsRecordBody::SSHFP { algorithm, fingerprint_type, fingerprint } => {
+                write!(f, "{}\t{}\t{}", algorithm, fingerprint_type, hex_encode(fingerprint))?;
+            },
+            DnsRecordBody::TLSA { usage, selector, matching_type, data } | DnsRecordBody::SMIMEA { usage, selector, matching_type, data } => {
+                write!(f, "{}\t{}\t{}\t{}", usage, selector, matching_type, hex_encode(data))?;
+            },
+            DnsRecordBody::OPENPGPKEY { public_key } => {
+                write!(f, "{}", base64_encode(public_key))?;
+            },
+            DnsRecordBody::RRSIG { type_covered, algorithm, labels, original_ttl, expiration, inception, key_tag, signer_name, signature } => {
+                write!(f, "{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}\t{}", type_covered, algorithm, labels, original_ttl,
+                       format_timestamp(*expiration), format_timestamp(*inception), key_tag, display_name(signer_name, f),
+                       base64_encode(signature))?;
+            },
+            DnsRecordBody::NSEC { next_domain, types } => {
+                write!(f, "{}", display_name(next_domain, f))?;
+                write_types(f, types)?;
+            },
+            DnsRecordBody::DNSKEY { flags, protocol, algorithm, public_key } => {
+                write!(f, "{}\t{}\t{}\t{}", flags, protocol, algorithm, base64_encode(public_key))?;
+            },
+            DnsRecordBody::NSEC3 { hash_algorithm, flags, iterations, salt, next_hashed_owner, types } => {
+                write!(f, "{}\t{}\t{}\t", hash_algorithm, flags, iterations)?;
+                write_salt(f, salt)?;
+                write!(f, "\t{}", base32hex_encode(next_hashed_owner))?;
+                write_types(f, types)?;
+            },
+            DnsRecordBody::NSEC3PARAM { hash_algorithm, flags, iterations, salt } => {
+                write!(f, "{}\t{}\t{}\t", hash_algorithm, flags, iterations)?;
+                write_salt(f, salt)?;
+            },
+            DnsRecordBody::SVCB { priority, target, params } | DnsRecordBody::HTTPS { priority, target, params } => {
+                write!(f, "{}\t{}", priority, display_name(target, f))?;
+                for param in params {
+                    write!(f, " {}", param)?;
                 }
             },
-            _ => {
-                let msg = format!("Skipping unknown record: {}", self);
-                return Err(Error::new(ErrorKind::InvalidInput, msg));
+            DnsRecordBody::CAA { flags, tag, value } => {
+                write!(f, "{}\t{}\t", flags, tag)?;
+                write_quoted(f, value)?;
+            },
+            DnsRecordBody::UNKNOWN { data, .. } => {
+                write!(f, "\\# {}", data.len())?;
+                if !data.is_empty() {
+                    write!(f, " {}", hex_encode(data))?;
+                }
             }
         }
         Ok(())
     }
 }
 
-impl std::fmt::Display for DnsRecord {
-    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "{}\t{}", self.preamble, self.body)?;
-        Ok(())
+// Names are shown as Unicode with the alternate flag, as in {:#}
+fn display_name(name: &DnsName, f: &std::fmt::Formatter) -> String {
+    if f.alternate() {
+        idn::to_unicode(&name.to_string())
+    } else {
+        name.to_string()
     }
 }
 
-pub struct DnsPacket {
-    pub header: DnsHeader,
-    pub questions: Vec<DnsQuestion>,
-    pub answers: Vec<DnsRecord>,
-    pub authorities: Vec<DnsRecord>,
-    pub additional: Vec<DnsRecord>,
+/// Writes a character-string in quotes, escaping quotes, backslashes and
+/// unprintable bytes as in a zone file.
+fn write_quoted(f: &mut std::fmt::Formatter, string: &[u8]) -> std::fmt::Result {
+    write!(f, "\"")?;
+    for &byte in string {
+        match byte {
+            b'"' | b'\\' => { write!(f, "\\{}", byte as char)?; },
+            0x20..=0x7E => { write!(f, "{}", byte as char)?; },
+            _ => { write!(f, "\\{:03}", byte)?; },
+        }
+    }
+    write!(f, "\"")?;
+    Ok(())
 }
 
-impl DnsPacket {
-    pub fn new() -> DnsPacket {
+fn write_types(f: &mut std::fmt::Formatter, types: &[RecordType]) -> std::fmt::Result {
+    for record_type in types {
+        write!(f, " {}", record_type)?;
+    }
+    Ok(())
+}
+
+/// NSEC3 salts are shown in hex, or as a dash when there is no salt.
+fn write_salt(f: &mut std::fmt::Formatter, salt: &[u8]) -> std::fmt::Result {
+    if salt.is_empty() {
+        write!(f, "-")?;
+    } else {
+        write!(f, "{}", hex_encode(salt))?;
+    }
+    Ok(())
+}
+
+/// Reads the remainder of a record's RDATA, such as a key or signature.
+fn read_bytes_until(buf: &mut DnsBuffer, end: usize) -> Result<Vec<u8>> {
+    let len = end.checked_sub(buf.pos)
+        .ok_or(DnsError::Malformed { offset: buf.pos, message: String::from("Record data overruns its length") })?;
+    let data = buf.get_range(buf.pos, len)?.to_vec();
+    buf.seek(end)?;
+    Ok(data)
+}
+
+/// Reads the type bitmap that ends NSEC and NSEC3 records.  Types are split
+/// into windows of 256, each with a bitmap of up to 32 bytes.  See section
+/// 4.1.2 of https://tools.ietf.org/html/rfc4034 for details.
+fn read_type_bitmap(buf: &mut DnsBuffer, end: usize) -> Result<Vec<RecordType>> {
+    let mut types = Vec::new();
+    let mut last_window = None;
+    while buf.pos < end {
+        let window_pos = buf.pos;
+        let window = buf.read()?;
+        let len = buf.read()? as usize;
+        if len == 0 || len > 32 || last_window.is_some_and(|last| window <= last) {
+            return Err(DnsError::Malformed { offset: window_pos, message: String::from("Invalid type bitmap window") });
+        }
+        last_window = Some(window);
+
+        for (idx, byte) in buf.get_range(buf.pos, len)?.iter().enumerate() {
+            for bit in 0..8 {
+                if byte & (0x80 >> bit) != 0 {
+                    types.push(RecordType::from_num((window as u16) << 8 | (idx * 8 + bit) as u16));
+                }
+            }
+        }
+        buf.seek(buf.pos + len)?;
+    }
+    Ok(types)
+}
+
+fn write_type_bitmap(buf: &mut DnsBuffer, types: &[RecordType]) -> Result<()> {
+    let mut nums: Vec<u16> = types.iter().map(RecordType::to_num).collect();
+    nums.sort_unstable();
+    nums.dedup();
+
+    let mut idx = 0;
+    while idx < nums.len() {
+        let window = nums[idx] >> 8;
+        let mut bitmap = [0_u8; 32];
+        let mut len = 0;
+        while idx < nums.len() && nums[idx] >> 8 == window {
+            let low = (nums[idx] & 0xFF) as usize;
+            bitmap[low / 8] |= 0x80 >> (low % 8);
+            len = low / 8 + 1;
+            idx += 1;
+        }
+        buf.write(window as u8)?;
+        buf.write(len as u8)?;
+        buf.write_bytes(&bitmap[..len])?;
+    }
+    Ok(())
+}
+
+/// A single SvcParam from an SVCB or HTTPS record, see section 7 of
+/// https://tools.ietf.org/html/rfc9460
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SvcParam {
+    // Keys the client must understand to use this record
+    Mandatory(Vec<u16>),
+    // Protocol identifiers such as h2 and h3, in order of preference
+    Alpn(Vec<Vec<u8>>),
+    // The default protocol (http/1.1 for HTTPS) isn't supported
+    NoDefaultAlpn,
+    Port(u16),
+    Ipv4Hint(Vec<Ipv4Addr>),
+    // Encrypted ClientHello configuration, see https://tools.ietf.org/html/draft-ietf-tls-esni
+    Ech(Vec<u8>),
+    Ipv6Hint(Vec<Ipv6Addr>),
+    Unknown {
+        key: u16,
+        value: Vec<u8>
+    },
+}
+
+impl SvcParam {
+    pub fn key(&self) -> u16 {
+        match self {
+            SvcParam::Mandatory(_) => 0,
+            SvcParam::Alpn(_) => 1,
+            SvcParam::NoDefaultAlpn => 2,
+            SvcParam::Port(_) => 3,
+            SvcParam::Ipv4Hint(_) => 4,
+            SvcParam::Ech(_) => 5,
+            SvcParam::Ipv6Hint(_) => 6,
+            SvcParam::Unknown { key, .. } => *key,
+        }
+    }
+
+    /// Parses the value of the param with the given key.  `offset` is where
+    /// the value starts, for error reporting.
+    pub fn from_value(key: u16, value: &[u8], offset: usize) -> Result<SvcParam> {
+        let invalid = |message: &str| DnsError::Malformed { offset, message: format!("Invalid {}: {}", svc_param_key_name(key), message) };
+        match key {
+            0 => {
+                if value.is_empty() || !value.len().is_multiple_of(2) {
+                    return Err(invalid("expected a list of keys"));
+                }
+                Ok(SvcParam::Mandatory(value.chunks(2).map(|pair| (pair[0] as u16) << 8 | pair[1] as u16).collect()))
+            },
+            1 => {
+                let mut ids = Vec::new();
+                let mut idx = 0;
+                while idx < value.len() {
+                    let len = value[idx] as usize;
+                    if len == 0 || idx + 1 + len > value.len() {
+                        return Err(invalid("bad protocol identifier"));
+                    }
+                    ids.push(value[idx + 1..idx + 1 + len].to_vec());
+                    idx += 1 + len;
+                }
+                if ids.is_empty() {
+                    return Err(invalid("expected at least one protocol"));
+                }
+                Ok(SvcParam::Alpn(ids))
+            },
+            2 => {
+                if !value.is_empty() {
+                    return Err(invalid("expected no value"));
+                }
+                Ok(SvcParam::NoDefaultAlpn)
+            },
+            3 => {
+                if value.len() != 2 {
+                    return Err(invalid("expected a 2 byte port"));
+                }
+                Ok(SvcParam::Port((value[0] as u16) << 8 | value[1] as u16))
+            },
+            4 => {
+                if value.is_empty() || !value.len().is_multiple_of(4) {
+                    return Err(invalid("expected a list of IPv4 addresses"));
+                }
+                Ok(SvcParam::Ipv4Hint(value.chunks(4).map(|octets| Ipv4Addr::new(octets[0], octets[1], octets[2], octets[3])).collect()))
+            },
+            5 => Ok(SvcParam::Ech(value.to_vec())),
+            6 => {
+                if value.is_empty() || !value.len().is_multiple_of(16) {
+                    return Err(invalid("expected a list of IPv6 addresses"));
+                }
+                Ok(SvcParam::Ipv6Hint(value.chunks(16).map(|octets| {
+                    let mut addr = [0_u8; 16];
+                    addr.copy_from_slice(octets);
+                    Ipv6Addr::from(addr)
+                }).collect()))
+            },
+            _ => Ok(SvcParam::Unknown { key, value: value.to_vec() }),
+        }
+    }
+
+    pub fn write(&self, buf: &mut DnsBuffer) -> Result<()> {
+        buf.write_u16(self.key())?;
+        let length_pos = buf.pos;
+        buf.write_u16(0)?;
+        match self {
+            SvcParam::Mandatory(keys) => {
+                for key in keys {
+                    buf.write_u16(*key)?;
+                }
+            },
+            SvcParam::Alpn(ids) => {
+                for id in ids {
+                    buf.write_character_string(id)?;
+                }
+            },
+            SvcParam::NoDefaultAlpn => {},
+            SvcParam::Port(port) => buf.write_u16(*port)?,
+            SvcParam::Ipv4Hint(addresses) => {
+                for address in addresses {
+                    buf.write_bytes(&address.octets())?;
+                }
+            },
+            SvcParam::Ech(config) => buf.write_bytes(config)?,
+            SvcParam::Ipv6Hint(addresses) => {
+                for address in addresses {
+                    buf.write_bytes(&address.octets())?;
+                }
+            },
+            SvcParam::Unknown { value, .. } => buf.write_bytes(value)?,
+        }
+        let size = buf.pos - (length_pos + 2);
+        buf.set_u16(length_pos, size as u16)?;
+        Ok(())
+    }
+}
+
+/// The presentation name of a SvcParam key, or keyNNNNN when unregistered.
+fn svc_param_key_name(key: u16) -> String {
+    match key {
+        0 => String::from("mandatory"),
+        1 => String::from("alpn"),
+        2 => String::from("no-default-alpn"),
+        3 => String::from("port"),
+        4 => String::from("ipv4hint"),
+        5 => String::from("ech"),
+        6 => String::from("ipv6hint"),
+        _ => format!("key{}", key),
+    }
+}
+
+impl std::fmt::Display for SvcParam {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", svc_param_key_name(self.key()))?;
+        match self {
+            SvcParam::Mandatory(keys) => {
+                let names: Vec<String> = keys.iter().map(|key| svc_param_key_name(*key)).collect();
+                write!(f, "={}", names.join(","))?;
+            },
+            SvcParam::Alpn(ids) => {
+                write!(f, "=")?;
+                for (idx, id) in ids.iter().enumerate() {
+                    if idx > 0 {
+                        write!(f, ",")?;
+                    }
+                    // Commas separate the identifiers, so any within one are escaped
+                    for &byte in id {
+                        match byte {
+                            b',' => { write!(f, "\\,")?; },
+                            b'\\' => { write!(f, "\\\\")?; },
+                            0x21..=0x7E if byte != b'"' => { write!(f, "{}", byte as char)?; },
+                            _ => { write!(f, "\\{:03}", byte)?; },
+                        }
+                    }
+                }
+            },
+            SvcParam::NoDefaultAlpn => {},
+            SvcParam::Port(port) => { write!(f, "={}", port)?; },
+            SvcParam::Ipv4Hint(addresses) => {
+                let addresses: Vec<String> = addresses.iter().map(|address| address.to_string()).collect();
+                write!(f, "={}", addresses.join(","))?;
+            },
+            SvcParam::Ech(config) => { write!(f, "={}", base64_encode(config))?; },
+            SvcParam::Ipv6Hint(addresses) => {
+                let addresses: Vec<String> = addresses.iter().map(|address| address.to_string()).collect();
+                write!(f, "={}", addresses.join(","))?;
+            },
+            SvcParam::Unknown { value, .. } => {
+                if !value.is_empty() {
+                    write!(f, "=")?;
+                    write_quoted(f, value)?;
+                }
+            },
+        }
+        Ok(())
+    }
+}
+
+/// Reads the SvcParams that fill the rest of an SVCB or HTTPS record.  Keys
+/// must appear in strictly increasing order, see section 2.2 of RFC 9460.
+fn read_svc_params(buf: &mut DnsBuffer, end: usize) -> Result<Vec<SvcParam>> {
+    let mut params = Vec::new();
+    let mut last_key = None;
+    while buf.pos < end {
+        let key_pos = buf.pos;
+        let key = buf.read_u16()?;
+        if last_key.is_some_and(|last| key <= last) {
+            return Err(DnsError::Malformed { offset: key_pos, message: String::from("SvcParam keys out of order") });
+        }
+        last_key = Some(key);
+
+        let len = buf.read_u16()? as usize;
+        if buf.pos + len > end {
+            return Err(DnsError::Malformed { offset: key_pos, message: String::from("SvcParam overruns its record") });
+        }
+        let value_pos = buf.pos;
+        let value = buf.get_range(value_pos, len)?.to_vec();
+        buf.seek(value_pos + len)?;
+        params.push(SvcParam::from_value(key, &value, value_pos)?);
+    }
+    Ok(params)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DnsRecord {
+    pub(crate) preamble: DnsRecordPreamble,
+    pub(crate) body: DnsRecordBody
+}
+
+impl DnsRecord {
+    pub fn new() -> DnsRecord {
+        DnsRecord {
+            preamble: DnsRecordPreamble::new(),
+            body: DnsRecordBody::UNKNOWN {
+                record_type: 0,
+                data: Vec::new()
+            }
+        }
+    }
+
+    /// A record of `name` in the IN class, its type taken from `body`.
+    pub fn from_body(name: DnsName, ttl: u32, body: DnsRecordBody) -> DnsRecord {
+        DnsRecord::from_body_class(name, RecordClass::IN, ttl, body)
+    }
+
+    pub fn from_body_class(name: DnsName, record_class: RecordClass, ttl: u32, body: DnsRecordBody) -> DnsRecord {
+        DnsRecord {
+            preamble: DnsRecordPreamble { name, record_type: body.record_type(), record_class, ttl, length: 0 },
+            body,
+        }
+    }
+
+    pub fn a(name: DnsName, ttl: u32, address: Ipv4Addr) -> DnsRecord {
+        DnsRecord::from_body(name, ttl, DnsRecordBody::A { address })
+    }
+
+    pub fn aaaa(name: DnsName, ttl: u32, address: Ipv6Addr) -> DnsRecord {
+        DnsRecord::from_body(name, ttl, DnsRecordBody::AAAA { address })
+    }
+
+    pub fn cname(name: DnsName, ttl: u32, target: DnsName) -> DnsRecord {
+        DnsRecord::from_body(name, ttl, DnsRecordBody::CNAME { name: target })
+    }
+
+    pub fn ns(name: DnsName, ttl: u32, nameserver: DnsName) -> DnsRecord {
+        DnsRecord::from_body(name, ttl, DnsRecordBody::NS { name: nameserver })
+    }
+
+    pub fn ptr(name: DnsName, ttl: u32, target: DnsName) -> DnsRecord {
+        DnsRecord::from_body(name, ttl, DnsRecordBody::PTR { name: target })
+    }
+
+    pub fn mx(name: DnsName, ttl: u32, priority: u16, exchange: DnsName) -> DnsRecord {
+        DnsRecord::from_body(name, ttl, DnsRecordBody::MX { priority, name: exchange })
+    }
+
+    /// A TXT record holding `text`, split into character-strings of up to
+    /// 255 bytes.
+    pub fn txt(name: DnsName, ttl: u32, text: &str) -> DnsRecord {
+        let strings = text.as_bytes().chunks(255).map(|chunk| chunk.to_vec()).collect();
+        DnsRecord::from_body(name, ttl, DnsRecordBody::TXT { strings })
+    }
+
+    pub fn srv(name: DnsName, ttl: u32, priority: u16, weight: u16, port: u16, target: DnsName) -> DnsRecord {
+        DnsRecord::from_body(name, ttl, DnsRecordBody::SRV { priority, weight, port, target })
+    }
+
+    pub fn caa(name: DnsName, ttl: u32, flags: u8, tag: &str, value: &[u8]) -> DnsRecord {
+        DnsRecord::from_body(name, ttl, DnsRecordBody::CAA { flags, tag: String::from(tag), value: value.to_vec() })
+    }
+
+    pub fn preamble(&self) -> &DnsRecordPreamble {
+        &self.preamble
+    }
+
+    pub fn body(&self) -> &DnsRecordBody {
+        &self.body
+    }
+
+    pub fn name(&self) -> &DnsName {
+        &self.preamble.name
+    }
+
+    pub fn record_type(&self) -> RecordType {
+        self.preamble.record_type
+    }
+
+    pub fn record_class(&self) -> RecordClass {
+        self.preamble.record_class
+    }
+
+    pub fn ttl(&self) -> u32 {
+        self.preamble.ttl
+    }
+
+    pub fn set_ttl(&mut self, ttl: u32) {
+        self.preamble.ttl = ttl;
+    }
+
+    /// Counts the TTL down by `seconds`, as for a cached record, stopping at
+    /// zero.
+    pub fn decrease_ttl(&mut self, seconds: u32) {
+        self.preamble.ttl = self.preamble.ttl.saturating_sub(seconds);
+    }
+
+    pub fn read(&mut self, buf: &mut DnsBuffer) -> Result<()> {
+        self.preamble.read(buf)?;
+        let start = buf.pos;
+        let end = start + self.preamble.length as usize;
+        self.body = self.body.read(&self.preamble.record_type, self.preamble.length, buf)?;
+
+        // Always continue from the end of the RDATA, so one misread record
+        // can't throw off the rest of the packet.
+        if buf.pos > end {
+            return Err(DnsError::Malformed { offset: start, message: format!("{} record overruns its length", self.preamble.record_type) });
+        }
+        buf.seek(end)?;
+        Ok(())
+    }
+
+    pub fn write(&self, buf: &mut DnsBuffer) -> Result<()> {
+        self.preamble.name.write(buf)?;
+        buf.write_u16(self.preamble.record_type.to_num())?;
+        buf.write_u16(self.preamble.record_class.to_num())?;
+        buf.write_u32(self.preamble.ttl)?;
+        let length_pos = buf.pos;
+        buf.write_u16(self.preamble.length)?;
+        
+        self.body.write(buf)?;
+
+        // Compressed names may be shorter than the length we read, so always
+        // write the size of the body that was actually written.
+        let size = buf.pos - (length_pos + 2);
+        buf.set_u16(length_pos, size as u16)?;
+        Ok(())
+    }
+}
+
+impl Default for DnsRecord {
+    fn default() -> Self {
+        DnsRecord::new()
+    }
+}
+
+impl std::fmt::Display for DnsRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        if f.alternate() {
+            write!(f, "{:#}\t{:#}", self.preamble, self.body)?;
+        } else {
+            write!(f, "{}\t{}", self.preamble, self.body)?;
+        }
+        Ok(())
+    }
+}
+
+/// A record with only the fields that matter to a caller, for matching on
+/// answers without the wire details of `DnsRecord`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub name: DnsName,
+    pub record_class: RecordClass,
+    pub ttl: u32,
+    // Holds the typed data, which also gives the record type
+    pub body: DnsRecordBody,
+}
+
+impl Record {
+    pub fn record_type(&self) -> RecordType {
+        self.body.record_type()
+    }
+}
+
+impl From<DnsRecord> for Record {
+    fn from(record: DnsRecord) -> Record {
+        Record {
+            name: record.preamble.name,
+            record_class: record.preamble.record_class,
+            ttl: record.preamble.ttl,
+            body: record.body,
+        }
+    }
+}
+
+impl From<Record> for DnsRecord {
+    fn from(record: Record) -> DnsRecord {
+        DnsRecord::from_body_class(record.name, record.record_class, record.ttl, record.body)
+    }
+}
+
+impl std::fmt::Display for Record {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}\t{}\t{}\t{}\t", display_name(&self.name, f), self.record_type(), self.record_class, self.ttl)?;
+        if f.alternate() {
+            write!(f, "{:#}", self.body)?;
+        } else {
+            write!(f, "{}", self.body)?;
+        }
+        Ok(())
+    }
+}
+
+/// A single EDNS option, such as a client subnet or cookie, kept as raw bytes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EdnsOption {
+    pub code: u16,
+    pub data: Vec<u8>,
+}
+
+impl std::fmt::Display for EdnsOption {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}: ", self.code)?;
+        for byte in &self.data {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+/// The EDNS0 OPT pseudo-record.  It lives in the additional section, but
+/// repurposes the class and TTL fields, so it is kept apart from the regular
+/// records.  See https://tools.ietf.org/html/rfc6891 for details.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edns {
+    // Largest UDP response the sender is able to receive
+    pub udp_payload_size: u16,
+    // Upper 8 bits of the 12 bit response code.  When a packet is read or
+    // written this follows the header's response code.
+    pub extended_rcode: u8,
+    pub version: u8,
+    // Set when the sender wants DNSSEC records in the response
+    pub dnssec_ok: bool,
+    // Remaining 15 flag bits, currently unused
+    pub z: u16,
+    pub options: Vec<EdnsOption>,
+}
+
+impl Edns {
+    pub fn new(udp_payload_size: u16) -> Edns {
+        Edns {
+            udp_payload_size,
+            extended_rcode: 0,
+            version: 0,
+            dnssec_ok: false,
+            z: 0,
+            options: Vec::new(),
+        }
+    }
+
+    /// Reads a full OPT record, starting from its (empty) owner name.
+    pub fn read(&mut self, buf: &mut DnsBuffer) -> Result<()> {
+        if !DnsName::read(buf)?.is_root() {
+            return Err(DnsError::Malformed { offset: buf.pos, message: String::from("OPT record must be owned by the root") });
+        }
+        if RecordType::from_num(buf.read_u16()?) != RecordType::OPT {
+            return Err(DnsError::Malformed { offset: buf.pos, message: String::from("Expected an OPT record") });
+        }
+        self.udp_payload_size = buf.read_u16()?;
+
+        let ttl = buf.read_u32()?;
+        self.extended_rcode = (ttl >> 24) as u8;
+        self.version = ((ttl >> 16) & 0xFF) as u8;
+        self.dnssec_ok = ttl & 0x8000 == 0x8000;
+        self.z = (ttl & 0x7FFF) as u16;
+
+        let length = buf.read_u16()? as usize;
+        let end = buf.pos + length;
+        self.options.clear();
+        while buf.pos < end {
+            let code = buf.read_u16()?;
+            let len = buf.read_u16()? as usize;
+            let data = buf.get_range(buf.pos, len)?.to_vec();
+            buf.seek(buf.pos + len)?;
+            self.options.push(EdnsOption { code, data });
+        }
+        if buf.pos != end {
+            return Err(DnsError::Malformed { offset: buf.pos, message: String::from("EDNS option overruns the OPT record") });
+        }
+        Ok(())
+    }
+
+    pub fn write(&self, buf: &mut DnsBuffer) -> Result<()> {
+        buf.write(0)?;
+        buf.write_u16(RecordType::OPT.to_num())?;
+        buf.write_u16(self.udp_payload_size)?;
+
+        let mut ttl = (self.extended_rcode as u32) << 24 |
+                      (self.version as u32) << 16 |
+                      (self.z & 0x7FFF) as u32;
+        if self.dnssec_ok {
+            ttl |= 0x8000;
+        }
+        buf.write_u32(ttl)?;
+
+        let length: usize = self.options.iter().map(|option| 4 + option.data.len()).sum();
+        buf.write_u16(length as u16)?;
+        for option in &self.options {
+            buf.write_u16(option.code)?;
+            buf.write_u16(option.data.len() as u16)?;
+            buf.write_bytes(&option.data)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::fmt::Display for Edns {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "version={} do={} udp={} extended_rcode={}", self.version, self.dnssec_ok,
+               self.udp_payload_size, self.extended_rcode)?;
+        for option in &self.options {
+            write!(f, "\n{}", option)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DnsPacket {
+    pub header: DnsHeader,
+    pub questions: Vec<DnsQuestion>,
+    pub answers: Vec<DnsRecord>,
+    pub authorities: Vec<DnsRecord>,
+    pub additional: Vec<DnsRecord>,
+    // The OPT pseudo-record, counted in the header as an additional record
+    pub edns: Option<Edns>,
+}
+
+impl DnsPacket {
+    pub fn new() -> DnsPacket {
         DnsPacket {
             header: DnsHeader::new(),
             questions: Vec::new(),
             answers: Vec::new(),
             authorities: Vec::new(),
             additional: Vec::new(),
+            edns: None,
         }
     }
 
     pub fn read(&mut self, buf: &mut DnsBuffer) -> Result<()> {
-        self.header.read(buf)?;
+        self.header.read(buf).map_err(|err| err.in_section(Section::Header))?;
 
         for _ in 0..self.header.question_count {
             let mut question = DnsQuestion::new();
-            question.read(buf)?;
+            question.read(buf).map_err(|err| err.in_section(Section::Question))?;
             self.questions.push(question);
         }
 
         for _ in 0..self.header.answer_count {
             let mut answer = DnsRecord::new();
-            answer.read(buf)?;
+            answer.read(buf).map_err(|err| err.in_section(Section::Answer))?;
             self.answers.push(answer);
         }
 
         for _ in 0..self.header.nameserver_count {
             let mut ns = DnsRecord::new();
-            ns.read(buf)?;
+            ns.read(buf).map_err(|err| err.in_section(Section::Authority))?;
             self.authorities.push(ns);
         }
 
         for _ in 0..self.header.additional_count {
+            self.read_additional(buf).map_err(|err| err.in_section(Section::Additional))?;
+        }
+
+        if let Some(edns) = &self.edns {
+            let code = (edns.extended_rcode as u16) << 4 | self.header.response_code.to_num();
+            self.header.response_code = ResponseCode::from_num(code);
+        }
+        Ok(())
+    }
+
+    fn read_additional(&mut self, buf: &mut DnsBuffer) -> Result<()> {
+        // Peek at the record type, since OPT records are read separately
+        let record_pos = buf.pos;
+        DnsName::read(buf)?;
+        let record_type = RecordType::from_num(buf.read_u16()?);
+        buf.seek(record_pos)?;
+
+        if record_type == RecordType::OPT {
+            if self.edns.is_some() {
+                return Err(DnsError::Malformed { offset: buf.pos, message: String::from("Packet contains more than one OPT record") });
+            }
+            let mut edns = Edns::new(0);
+            edns.read(buf)?;
+            self.edns = Some(edns);
+        } else {
             let mut record = DnsRecord::new();
             record.read(buf)?;
             self.additional.push(record);
@@ -621,72 +2105,171 @@ impl DnsPacket {
         Ok(())
     }
 
-    pub fn from_query(hostname: &String, query_type: &String) -> Result<DnsPacket> {
-        let mut packet = DnsPacket::new();
-        packet.header.recursion_desired = true;
-        packet.header.question_count = 1;
+    /// How long a negative answer (NXDOMAIN or NODATA) may be cached, taken from
+    /// the SOA record in the authority section.  As per section 5 of
+    /// https://tools.ietf.org/html/rfc2308 this is the lesser of the SOA's own
+    /// TTL and its MINIMUM field.
+    pub fn negative_ttl(&self) -> Option<u32> {
+        self.authorities.iter().find_map(|record| match record.body {
+            DnsRecordBody::SOA { minimum, .. } => Some(record.preamble.ttl.min(minimum)),
+            _ => None,
+        })
+    }
+
+    /// The answer section as typed records.
+    pub fn answer_records(&self) -> Vec<Record> {
+        self.answers.iter().cloned().map(Record::from).collect()
+    }
+
+    /// Advertises EDNS0 support, replacing any existing OPT record.
+    pub fn add_edns(&mut self, udp_payload_size: u16) {
+        if self.edns.is_none() {
+            self.header.additional_count += 1;
+        }
+        self.edns = Some(Edns::new(udp_payload_size));
+    }
+
+    /// Drops the OPT record, for replying to senders that don't understand it.
+    pub fn remove_edns(&mut self) {
+        if self.edns.take().is_some() {
+            self.header.additional_count -= 1;
+        }
+    }
+
+    /// The largest UDP message the sender of this packet is able to receive.
+    pub fn max_udp_size(&self) -> usize {
+        match &self.edns {
+            Some(edns) => (edns.udp_payload_size as usize).max(UDP_MAX_SIZE),
+            None => UDP_MAX_SIZE,
+        }
+    }
+
+    pub fn from_query(hostname: &str, query_type: &str) -> Result<DnsPacket> {
+        DnsPacket::from_query_class(hostname, query_type, "IN")
+    }
+
+    /// Builds a query for a class other than IN, such as a CH query for
+    /// version.bind.
+    pub fn from_query_class(hostname: &str, query_type: &str, query_class: &str) -> Result<DnsPacket> {
+        // Unicode names are sent as xn-- A-labels
+        let name = idn::to_ascii(hostname)?.parse()?;
+        let question = DnsQuestion::query_class(name, query_type.parse()?, query_class.parse()?);
+        Ok(DnsPacketBuilder::query().question(question).build())
+    }
 
-        let mut question = DnsQuestion::new();
-        question.name = String::from(hostname);
-        question.record_type =  RecordType::from_str(query_type);
-        question.record_class =  RecordClass::IN;
-        packet.questions.push(question);
+    /// Builds a PTR query for the reverse lookup of an address.
+    pub fn from_reverse_query(addr: IpAddr) -> Result<DnsPacket> {
+        DnsPacket::from_query(&reverse::reverse_name(addr), "PTR")
+    }
+
+    /// Sets the header counts from the sections, counting the OPT record as
+    /// an additional record.  A section too large to count is left for
+    /// `write` to reject.
+    pub fn update_counts(&mut self) {
+        if let Ok(header) = self.counted_header() {
+            self.header = header;
+        }
+    }
 
-        Ok(packet)
+    fn counted_header(&self) -> Result<DnsHeader> {
+        let count = |len: usize| u16::try_from(len).map_err(|_| {
+            DnsError::InvalidInput(format!("Section of {} entries is too large for a packet", len))
+        });
+        let mut header = self.header;
+        header.question_count = count(self.questions.len())?;
+        header.answer_count = count(self.answers.len())?;
+        header.nameserver_count = count(self.authorities.len())?;
+        header.additional_count = count(self.additional.len() + self.edns.is_some() as usize)?;
+        Ok(header)
     }
 
+    /// Writes the packet.  The header counts written are taken from the
+    /// sections, whatever the counts in `header` say.
     pub fn write(&self, buf: &mut DnsBuffer) -> Result<()> {
-        self.header.write(buf)?;
+        // Without an OPT record the upper bits would be lost, sending a
+        // different response code
+        if self.header.response_code.to_num() > 0x0F && self.edns.is_none() {
+            return Err(DnsError::InvalidInput(format!("Response code {} needs an OPT record", self.header.response_code)));
+        }
+        self.counted_header()?.write(buf)?;
 
-        for idx in 0..self.header.question_count {
-            self.questions[idx as usize].write(buf)?;
+        for question in &self.questions {
+            question.write(buf)?;
         }
 
-        for idx in 0..self.header.answer_count {
-            self.answers[idx as usize].write(buf)?;
+        for record in &self.answers {
+            record.write(buf)?;
         }
-        
-        for idx in 0..self.header.nameserver_count {
-            self.authorities[idx as usize].write(buf)?;
+
+        for record in &self.authorities {
+            record.write(buf)?;
         }
-        
-        for idx in 0..self.header.nameserver_count {
-            self.additional[idx as usize].write(buf)?;
+
+        for record in &self.additional {
+            record.write(buf)?;
+        }
+
+        // Response codes above 15 only fit with the help of the OPT record
+        if let Some(edns) = &self.edns {
+            let mut edns = edns.clone();
+            edns.extended_rcode = (self.header.response_code.to_num() >> 4) as u8;
+            edns.write(buf)?;
         }
 
         Ok(())
     }
 }
 
+impl Default for DnsPacket {
+    fn default() -> Self {
+        DnsPacket::new()
+    }
+}
+
+/// Shows the packet in sections.  The alternate form, `{:#}`, shows
+/// internationalized names as Unicode rather than as `xn--` A-labels.
 impl std::fmt::Display for DnsPacket {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        fn write_item<T: std::fmt::Display>(f: &mut std::fmt::Formatter, item: &T) -> std::fmt::Result {
+            if f.alternate() {
+                writeln!(f, "{:#}", item)
+            } else {
+                writeln!(f, "{}", item)
+            }
+        }
+
         writeln!(f, "Header")?;
         writeln!(f, "------")?;
         writeln!(f, "{}", self.header)?;
-        writeln!(f, "")?;
+        writeln!(f)?;
         writeln!(f, "Questions")?;
         writeln!(f, "---------")?;
-        for idx in 0..self.header.question_count {
-            writeln!(f, "{}", self.questions[idx as usize])?;
-            writeln!(f, "")?;
+        for question in &self.questions {
+            write_item(f, question)?;
+            writeln!(f)?;
         }
         writeln!(f, "Answers")?;
         writeln!(f, "-------")?;
-        for idx in 0..self.header.answer_count {
-            writeln!(f, "{}", self.answers[idx as usize])?;
-            writeln!(f, "")?;
+        for record in &self.answers {
+            write_item(f, record)?;
+            writeln!(f)?;
         }
         writeln!(f, "Authorities")?;
         writeln!(f, "-----------")?;
-        for idx in 0..self.header.nameserver_count {
-            writeln!(f, "{}", self.authorities[idx as usize])?;
-            writeln!(f, "")?;
+        for record in &self.authorities {
+            write_item(f, record)?;
+            writeln!(f)?;
         }
         writeln!(f, "Additional")?;
         writeln!(f, "----------")?;
-        for idx in 0..self.header.additional_count {
-            writeln!(f, "{}", self.additional[idx as usize])?;
-            writeln!(f, "")?;
+        for record in &self.additional {
+            write_item(f, record)?;
+            writeln!(f)?;
+        }
+        if let Some(edns) = &self.edns {
+            writeln!(f, "EDNS")?;
+            writeln!(f, "----")?;
+            writeln!(f, "{}", edns)?;
         }
         
         Ok(())
@@ -695,12 +2278,16 @@ impl std::fmt::Display for DnsPacket {
 
 #[cfg(test)]
 mod tests {
+    use std::fs::File;
+    use std::io::Read;
+
     use super::*;
 
+    #[allow(clippy::bool_assert_comparison)]
     fn assert_header(header: &DnsHeader) {
         assert_eq!(header.id, 29600);
         assert_eq!(header.query_response, true);
-        assert_eq!(header.opcode, 0);
+        assert_eq!(header.opcode, Opcode::QUERY);
         assert_eq!(header.authoritative_answer, false);
         assert_eq!(header.truncated_message, false);
         assert_eq!(header.recursion_desired, true);
@@ -724,7 +2311,8 @@ mod tests {
     fn test_parse() {
         let mut buf = DnsBuffer::new();
         let mut f = File::open("response.txt").unwrap();
-        f.read(&mut buf.buf).unwrap();
+        let bytes_read = f.read(&mut buf.buf).unwrap();
+        buf.set_len(bytes_read).unwrap();
 
         let mut header = DnsHeader::new();
         header.read(&mut buf).unwrap();
@@ -766,7 +2354,8 @@ mod tests {
     fn test_parse_packet() {
         let mut buf = DnsBuffer::new();
         let mut f = File::open("response.txt").unwrap();
-        f.read(&mut buf.buf).unwrap();
+        let bytes_read = f.read(&mut buf.buf).unwrap();
+        buf.set_len(bytes_read).unwrap();
 
         let mut packet = DnsPacket::new();
         packet.read(&mut buf).unwrap();
@@ -797,17 +2386,19 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison, clippy::useless_vec)]
     fn test_parse_yahoo() {
         let mut buf = DnsBuffer::new();
         let mut f = File::open("www.yahoo.com.response.txt").unwrap();
-        f.read(&mut buf.buf).unwrap();
+        let bytes_read = f.read(&mut buf.buf).unwrap();
+        buf.set_len(bytes_read).unwrap();
         
 
         let mut packet = DnsPacket::new();
         packet.read(&mut buf).unwrap();
         assert_eq!(packet.header.id, 49323);
         assert_eq!(packet.header.query_response, true);
-        assert_eq!(packet.header.opcode, 0);
+        assert_eq!(packet.header.opcode, Opcode::QUERY);
         assert_eq!(packet.header.authoritative_answer, false);
         assert_eq!(packet.header.truncated_message, false);
         assert_eq!(packet.header.recursion_desired, true);
@@ -852,6 +2443,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn test_write_packet() {
         let packet = DnsPacket::from_query(&String::from("google.com"), &String::from("A")).unwrap();
         assert!(packet.header.recursion_desired);
@@ -864,9 +2456,9 @@ mod tests {
         let mut new_packet = DnsPacket::new();
         new_packet.read(&mut buf).unwrap();
 
-        assert_eq!(new_packet.header.id, 0);
+        assert_eq!(new_packet.header.id, packet.header.id);
         assert_eq!(new_packet.header.query_response, false);
-        assert_eq!(new_packet.header.opcode, 0);
+        assert_eq!(new_packet.header.opcode, Opcode::QUERY);
         assert_eq!(new_packet.header.authoritative_answer, false);
         assert_eq!(new_packet.header.truncated_message, false);
         assert_eq!(new_packet.header.recursion_desired, true);
@@ -882,4 +2474,613 @@ mod tests {
         assert_eq!(new_packet.questions[0].record_type, RecordType::A);
         assert_eq!(new_packet.questions[0].record_class, RecordClass::IN);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_write_stale_counts() {
+        let mut packet = DnsPacket::from_query("example.com", "A").unwrap();
+        let mut record = DnsRecord::new();
+        record.preamble.name = "example.com".parse().unwrap();
+        record.preamble.record_type = RecordType::A;
+        record.preamble.record_class = RecordClass::IN;
+        record.body = DnsRecordBody::A { address: Ipv4Addr::new(93, 184, 216, 34) };
+        packet.answers.push(record.clone());
+        packet.additional.push(record);
+        packet.header.nameserver_count = 3;
+
+        let mut buf = DnsBuffer::new();
+        packet.write(&mut buf).unwrap();
+        buf.pos = 0;
+        let mut new_packet = DnsPacket::new();
+        new_packet.read(&mut buf).unwrap();
+        assert_eq!(new_packet.header.answer_count, 1);
+        assert_eq!(new_packet.header.nameserver_count, 0);
+        assert_eq!(new_packet.header.additional_count, 1);
+        assert_eq!(new_packet.additional.len(), 1);
+    }
+
+    #[test]
+    fn test_record_api() {
+        let name: DnsName = "example.com".parse().unwrap();
+        let mut record = DnsRecord::mx(name.clone(), 3600, 10, "mail.example.com".parse().unwrap());
+        assert_eq!(record.name(), &name);
+        assert_eq!(record.record_type(), RecordType::MX);
+        assert_eq!(record.record_class(), RecordClass::IN);
+        assert_eq!(record.ttl(), 3600);
+        record.decrease_ttl(600);
+        assert_eq!(record.ttl(), 3000);
+        record.decrease_ttl(5000);
+        assert_eq!(record.ttl(), 0);
+        record.set_ttl(60);
+
+        let txt = DnsRecord::txt(name.clone(), 60, &"a".repeat(300));
+        match txt.body() {
+            DnsRecordBody::TXT { strings } => assert_eq!(strings.iter().map(Vec::len).collect::<Vec<_>>(), vec![255, 45]),
+            body => panic!("Unexpected body {}", body),
+        }
+
+        let query = DnsPacket::from_query("example.com", "MX").unwrap();
+        assert_eq!(query.questions[0].name(), &name);
+        assert_eq!(query.questions[0].record_type(), RecordType::MX);
+        let response = DnsPacketBuilder::response(&query).answer(record.clone()).answer(txt).build();
+
+        let mut buf = DnsBuffer::new();
+        response.write(&mut buf).unwrap();
+        buf.pos = 0;
+        let mut packet = DnsPacket::new();
+        packet.read(&mut buf).unwrap();
+
+        let records = packet.answer_records();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0], Record::from(record.clone()));
+        assert_eq!(records[0].record_type(), RecordType::MX);
+        assert_eq!(records[0].to_string(), "example.com\tMX\tIN\t60\tmail.example.com\t10");
+        assert_eq!(DnsRecord::from(records[0].clone()), record);
+        assert!(matches!(&records[1].body, DnsRecordBody::TXT { .. }));
+    }
+
+    #[test]
+    fn test_display_stale_counts() {
+        let mut packet = DnsPacket::from_query("example.com", "A").unwrap();
+        packet.answers.push(DnsRecord::a("example.com".parse().unwrap(), 300, Ipv4Addr::new(192, 0, 2, 1)));
+        packet.header.question_count = 2;
+        let output = packet.to_string();
+        assert!(output.contains("example.com\tA\tIN\t300\t0\t192.0.2.1"));
+    }
+
+    #[test]
+    fn test_write_compressed_packet() {
+        let mut buf = DnsBuffer::new();
+        let mut f = File::open("www.yahoo.com.response.txt").unwrap();
+        let bytes_read = f.read(&mut buf.buf).unwrap();
+        buf.set_len(bytes_read).unwrap();
+
+        let mut packet = DnsPacket::new();
+        packet.read(&mut buf).unwrap();
+
+        let mut out = DnsBuffer::new();
+        packet.write(&mut out).unwrap();
+        assert_eq!(out.pos, bytes_read);
+        assert_eq!(&out.buf[..out.pos], &buf.buf[..bytes_read]);
+    }
+
+    #[test]
+    fn test_write_edns() {
+        let mut packet = DnsPacket::from_query("google.com", "A").unwrap();
+        packet.add_edns(4096);
+        if let Some(edns) = &mut packet.edns {
+            edns.dnssec_ok = true;
+            edns.options.push(EdnsOption { code: 10, data: vec![1, 2, 3, 4, 5, 6, 7, 8] });
+        }
+        assert_eq!(packet.header.additional_count, 1);
+
+        let mut buf = DnsBuffer::new();
+        packet.write(&mut buf).unwrap();
+        buf.pos = 0;
+
+        let mut new_packet = DnsPacket::new();
+        new_packet.read(&mut buf).unwrap();
+        assert_eq!(new_packet.header.additional_count, 1);
+        assert!(new_packet.additional.is_empty());
+        assert_eq!(new_packet.edns, packet.edns);
+        assert_eq!(new_packet.max_udp_size(), 4096);
+
+        new_packet.remove_edns();
+        assert_eq!(new_packet.header.additional_count, 0);
+        assert_eq!(new_packet.max_udp_size(), UDP_MAX_SIZE);
+    }
+
+    #[test]
+    fn test_parse_edns() {
+        // dig's query for example.com, with a cookie option and the DO bit set
+        let bytes = [
+            0x12, 0x34, 0x01, 0x20, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x01,
+            0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x03, b'c', b'o', b'm', 0x00,
+            0x00, 0x01, 0x00, 0x01,
+            0x00, 0x00, 0x29, 0x04, 0xD0, 0x00, 0x00, 0x80, 0x00, 0x00, 0x0C,
+            0x00, 0x0A, 0x00, 0x08, 0xDE, 0xAD, 0xBE, 0xEF, 0xDE, 0xAD, 0xBE, 0xEF,
+        ];
+        let mut buf = DnsBuffer::from_bytes(&bytes);
+        let mut packet = DnsPacket::new();
+        packet.read(&mut buf).unwrap();
+
+        let edns = packet.edns.as_ref().unwrap();
+        assert_eq!(edns.udp_payload_size, 1232);
+        assert_eq!(edns.extended_rcode, 0);
+        assert_eq!(edns.version, 0);
+        assert!(edns.dnssec_ok);
+        assert_eq!(edns.options, vec![EdnsOption { code: 10, data: vec![0xDE, 0xAD, 0xBE, 0xEF, 0xDE, 0xAD, 0xBE, 0xEF] }]);
+
+        let mut out = DnsBuffer::new();
+        packet.write(&mut out).unwrap();
+        assert_eq!(out.as_bytes(), &bytes[..]);
+    }
+
+    #[test]
+    fn test_parse_truncated() {
+        let mut buf = DnsBuffer::new();
+        let mut f = File::open("www.yahoo.com.response.txt").unwrap();
+        let bytes_read = f.read(&mut buf.buf).unwrap();
+        buf.set_len(bytes_read - 10).unwrap();
+
+        let mut packet = DnsPacket::new();
+        match packet.read(&mut buf) {
+            Err(err @ DnsError::Section { .. }) => {
+                assert_eq!(err.section(), Some(Section::Answer));
+                assert_eq!(err.offset(), Some(bytes_read - 10));
+            },
+            _ => panic!("A truncated packet should fail in the answer section"),
+        }
+    }
+
+    // Packets crafted to hang or crash a careless parser, along with the section
+    // and error each one must be rejected with
+    #[test]
+    fn test_malicious_packets() {
+        fn packet(counts: [u16; 4], body: &[u8]) -> Vec<u8> {
+            let mut bytes = vec![0xBE, 0xEF, 0x81, 0x80];
+            for count in &counts {
+                bytes.extend_from_slice(&count.to_be_bytes());
+            }
+            bytes.extend_from_slice(body);
+            bytes
+        }
+        let long_name = [&[&b"\x3F"[..], &[b'a'; 63][..]].concat()[..]; 4].concat();
+        type Expected = fn(&DnsError) -> bool;
+        let corpus: Vec<(&str, Vec<u8>, Section, Expected)> = vec![
+            ("pointer to itself", packet([1, 0, 0, 0], b"\xC0\x0C\x00\x01\x00\x01"),
+             Section::Question, |err| matches!(err, DnsError::PointerLoop { offset: 12 })),
+            ("two pointers to each other", packet([1, 0, 0, 0], b"\xC0\x0E\xC0\x0C\x00\x01\x00\x01"),
+             Section::Question, |err| matches!(err, DnsError::ForwardPointer { offset: 12 })),
+            ("label pointing into itself", packet([1, 0, 0, 0], b"\x01a\xC0\x0C\x00\x01\x00\x01"),
+             Section::Question, |err| matches!(err, DnsError::PointerLoop { .. })),
+            ("owner name pointing forwards", packet([1, 1, 0, 0], b"\x01a\x00\x00\x01\x00\x01\xC0\x20\x00\x01\x00\x01\x00\x00\x00\x3C\x00\x04\x01\x02\x03\x04"),
+             Section::Answer, |err| matches!(err, DnsError::ForwardPointer { offset: 19 })),
+            ("CNAME pointing to itself", packet([1, 1, 0, 0], b"\x01a\x00\x00\x05\x00\x01\xC0\x0C\x00\x05\x00\x01\x00\x00\x00\x3C\x00\x02\xC0\x1F"),
+             Section::Answer, |err| matches!(err, DnsError::PointerLoop { offset: 31 })),
+            ("extended label type", packet([1, 0, 0, 0], b"\x41a\x00\x00\x01\x00\x01"),
+             Section::Question, |err| matches!(err, DnsError::ReservedLabelType { offset: 12 })),
+            ("reserved 0x80 label type", packet([1, 0, 0, 0], b"\x01a\x81a\x00\x00\x01\x00\x01"),
+             Section::Question, |err| matches!(err, DnsError::ReservedLabelType { offset: 14 })),
+            ("257 byte name", packet([1, 0, 0, 0], &[&long_name[..], b"\x00\x00\x01\x00\x01"].concat()),
+             Section::Question, |err| matches!(err, DnsError::NameTooLong { .. })),
+            ("pointer cut short", packet([1, 0, 0, 0], b"\x01a\xC0"),
+             Section::Question, |err| matches!(err, DnsError::Truncated { .. })),
+            ("label cut short", packet([1, 0, 0, 0], b"\x3Fabc"),
+             Section::Question, |err| matches!(err, DnsError::Truncated { .. })),
+            ("more questions than the packet holds", packet([0xFFFF, 0, 0, 0], b"\x00\x00\x01\x00\x01"),
+             Section::Question, |err| matches!(err, DnsError::Truncated { .. })),
+        ];
+
+        for (description, bytes, section, expected) in corpus {
+            let mut packet = DnsPacket::new();
+            let err = packet.read(&mut DnsBuffer::from_bytes(&bytes)).expect_err(description);
+            assert_eq!(err.section(), Some(section), "{}", description);
+            match err {
+                DnsError::Section { error, .. } => assert!(expected(&error), "{}: {}", description, error),
+                _ => panic!("{}: error has no section", description),
+            }
+        }
+    }
+
+    #[test]
+    fn test_unknown_record() {
+        // A TYPE65280 record followed by an A record, both for example.com
+        let bytes = [
+            0x12, 0x34, 0x81, 0x80, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00,
+            0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x03, b'c', b'o', b'm', 0x00,
+            0xFF, 0x00, 0x00, 0x01, 0x00, 0x00, 0x0E, 0x10, 0x00, 0x05,
+            0x0A, 0x00, 0x00, 0x01, 0xC0,
+            0xC0, 0x0C, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x0E, 0x10, 0x00, 0x04,
+            0x0A, 0x00, 0x00, 0x02,
+        ];
+        let mut buf = DnsBuffer::from_bytes(&bytes);
+        let mut packet = DnsPacket::new();
+        packet.read(&mut buf).unwrap();
+
+        assert_eq!(packet.answers[0].preamble.record_type, RecordType::UNKNOWN(65280));
+        assert_eq!(packet.answers[0].body, DnsRecordBody::UNKNOWN { record_type: 65280, data: vec![0x0A, 0x00, 0x00, 0x01, 0xC0] });
+        assert_eq!(packet.answers[0].to_string(), "example.com\tTYPE65280\tIN\t3600\t5\t\\# 5 0A000001C0");
+        assert_eq!(packet.answers[1].body, DnsRecordBody::A { address: Ipv4Addr::new(10, 0, 0, 2) });
+
+        let mut out = DnsBuffer::new();
+        packet.write(&mut out).unwrap();
+        assert_eq!(out.as_bytes(), &bytes[..]);
+
+        assert_eq!("TYPE65280".parse::<RecordType>().unwrap(), RecordType::UNKNOWN(65280));
+        assert_eq!("TYPE1".parse::<RecordType>().unwrap(), RecordType::A);
+        assert!("TYPEA".parse::<RecordType>().is_err());
+    }
+
+    #[test]
+    fn test_record_type_from_str() {
+        assert_eq!(RecordType::from_str(&String::from("MX")), RecordType::MX);
+        assert_eq!(RecordType::from_str(&String::from("TYPE65280")), RecordType::UNKNOWN(65280));
+        assert_eq!(RecordType::from_str(&String::from("BOGUS")), RecordType::UNKNOWN(0));
+    }
+
+    #[test]
+    fn test_txt_record() {
+        let mut long = vec![b'a'; 255];
+        long[0] = b'"';
+        long[1] = 0x07;
+        let mut record = DnsRecord::new();
+        record.preamble.name = "example.com".parse().unwrap();
+        record.preamble.record_type = RecordType::TXT;
+        record.preamble.record_class = RecordClass::IN;
+        record.body = DnsRecordBody::TXT { strings: vec![b"v=spf1 -all".to_vec(), Vec::new(), long] };
+
+        let mut buf = DnsBuffer::new();
+        record.write(&mut buf).unwrap();
+        buf.pos = 0;
+
+        let mut new_record = DnsRecord::new();
+        new_record.read(&mut buf).unwrap();
+        assert_eq!(new_record.preamble.length, 12 + 1 + 256);
+        assert_eq!(new_record.body, record.body);
+
+        // Longer strings have to be split by the caller
+        record.body = DnsRecordBody::TXT { strings: vec![vec![b'a'; 256]] };
+        assert!(matches!(record.write(&mut DnsBuffer::new()), Err(DnsError::InvalidInput(_))));
+
+        // No strings at all is written as a single empty string
+        record.body = DnsRecordBody::TXT { strings: Vec::new() };
+        let mut buf = DnsBuffer::new();
+        record.write(&mut buf).unwrap();
+        buf.pos = 0;
+        new_record.read(&mut buf).unwrap();
+        assert_eq!(new_record.body, DnsRecordBody::TXT { strings: vec![Vec::new()] });
+
+        let body = DnsRecordBody::TXT { strings: vec![b"say \"hi\"".to_vec(), b"back\\slash\x01".to_vec()] };
+        assert_eq!(body.to_string(), "\"say \\\"hi\\\"\" \"back\\\\slash\\001\"");
+        assert_eq!("TXT".parse::<RecordType>().unwrap(), RecordType::TXT);
+    }
+
+    #[test]
+    fn test_soa_record() {
+        // NXDOMAIN for nope.example.com, with the example.com SOA in the authority section
+        let bytes = [
+            0xAB, 0xCD, 0x81, 0x83, 0x00, 0x01, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00,
+            0x04, b'n', b'o', b'p', b'e', 0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x03, b'c', b'o', b'm', 0x00,
+            0x00, 0x01, 0x00, 0x01,
+            0xC0, 0x11, 0x00, 0x06, 0x00, 0x01, 0x00, 0x00, 0x0E, 0x10, 0x00, 0x2C,
+            0x02, b'n', b's', 0x05, b'i', b'c', b'a', b'n', b'n', 0x03, b'o', b'r', b'g', 0x00,
+            0x03, b'n', b'o', b'c', 0x03, b'd', b'n', b's', 0xC0, 0x31,
+            0x78, 0x3A, 0x5C, 0x29, 0x00, 0x00, 0x1C, 0x20, 0x00, 0x00, 0x0E, 0x10,
+            0x00, 0x12, 0x75, 0x00, 0x00, 0x00, 0x01, 0x2C,
+        ];
+        let mut buf = DnsBuffer::from_bytes(&bytes);
+        let mut packet = DnsPacket::new();
+        packet.read(&mut buf).unwrap();
+
+        assert_eq!(packet.header.response_code, ResponseCode::NXDOMAIN);
+        assert_eq!(packet.authorities[0].preamble.name, "example.com");
+        assert_eq!(packet.authorities[0].body, DnsRecordBody::SOA {
+            mname: "ns.icann.org".parse().unwrap(),
+            rname: "noc.dns.icann.org".parse().unwrap(),
+            serial: 2017090601,
+            refresh: 7200,
+            retry: 3600,
+            expire: 1209600,
+            minimum: 300,
+        });
+        assert_eq!(packet.negative_ttl(), Some(300));
+
+        let mut out = DnsBuffer::new();
+        packet.write(&mut out).unwrap();
+        assert_eq!(out.as_bytes(), &bytes[..]);
+    }
+
+    #[test]
+    fn test_reverse_query() {
+        let packet = DnsPacket::from_reverse_query(IpAddr::V4(Ipv4Addr::new(8, 8, 4, 4))).unwrap();
+        assert_eq!(packet.questions[0].name, "4.4.8.8.in-addr.arpa");
+        assert_eq!(packet.questions[0].record_type, RecordType::PTR);
+
+        let mut buf = DnsBuffer::new();
+        let mut record = DnsRecord::new();
+        record.preamble.name = packet.questions[0].name.clone();
+        record.preamble.record_type = RecordType::PTR;
+        record.preamble.record_class = RecordClass::IN;
+        record.body = DnsRecordBody::PTR { name: "dns.google".parse().unwrap() };
+        record.write(&mut buf).unwrap();
+        buf.pos = 0;
+
+        let mut new_record = DnsRecord::new();
+        new_record.read(&mut buf).unwrap();
+        assert_eq!(new_record, DnsRecord { preamble: DnsRecordPreamble { length: 12, ..record.preamble.clone() }, ..record });
+    }
+
+    fn roundtrip(record_type: RecordType, body: DnsRecordBody) -> (DnsRecord, DnsBuffer) {
+        let mut record = DnsRecord::new();
+        record.preamble.name = "example.com".parse().unwrap();
+        record.preamble.record_type = record_type;
+        record.preamble.record_class = RecordClass::IN;
+        record.preamble.ttl = 3600;
+        record.body = body;
+
+        let mut buf = DnsBuffer::new();
+        record.write(&mut buf).unwrap();
+        buf.pos = 0;
+
+        let mut new_record = DnsRecord::new();
+        new_record.read(&mut buf).unwrap();
+        assert_eq!(new_record.body, record.body);
+        (new_record, buf)
+    }
+
+    #[test]
+    fn test_dnskey_record() {
+        // The root zone's 2017 key signing key
+        let public_key = crate::encoding::base64_decode("AwEAAaz/tAm8yTn4Mfeh5eyI96WSVexTBAvkMgJzkKTOiW1vkIbzxeF3+/4RgWOq7HrxRixHlFlExOLAJr5emLvN7SWXgnLh4+B5xQlNVz8Og8kvArMtNROxVQuCaSnIDdD5LKyWbRd2n9WGe2R8PzgCmr3EgVLrjyBxWezF0jLHwVN8efS3rCj/EWgvIWgb9tarpVUDK/b58Da+sqqls3eNbuv7pr+eoZG+SrDK6nWeL3c6H5Apxz7LjVc1uTIdsIXxuOLYA4/ilBmSVIzuDWfdRUfhHdY6+cn8HFRm+2hM8AnXGXws9555KrUB5qihylGa8subX2Nn6UwNR1AkUTV74bU=").unwrap();
+        let body = DnsRecordBody::DNSKEY { flags: 257, protocol: 3, algorithm: 8, public_key };
+        assert_eq!(body.key_tag(), Some(20326));
+        assert!(body.to_string().starts_with("257\t3\t8\tAwEAAaz/tAm8"));
+        roundtrip(RecordType::DNSKEY, body);
+
+        let body = DnsRecordBody::DS { key_tag: 20326, algorithm: 8, digest_type: 2, digest: vec![0xE0, 0x6D, 0x44, 0xB8] };
+        assert_eq!(body.to_string(), "20326\t8\t2\tE06D44B8");
+        assert_eq!(body.key_tag(), None);
+        roundtrip(RecordType::DS, body);
+    }
+
+    #[test]
+    fn test_rrsig_record() {
+        let body = DnsRecordBody::RRSIG {
+            type_covered: RecordType::A,
+            algorithm: 13,
+            labels: 2,
+            original_ttl: 3600,
+            expiration: 1700000000,
+            inception: 1698796800,
+            key_tag: 12345,
+            signer_name: "example.com".parse().unwrap(),
+            signature: vec![0xDE, 0xAD, 0xBE, 0xEF],
+        };
+        assert_eq!(body.to_string(), "A\t13\t2\t3600\t20231114221320\t20231101000000\t12345\texample.com\t3q2+7w==");
+        let (record, _) = roundtrip(RecordType::RRSIG, body);
+        // The signer name repeats the owner name, but must not be compressed
+        assert_eq!(record.preamble.length, 18 + 13 + 4);
+    }
+
+    #[test]
+    fn test_nsec_record() {
+        // The example from section 4.3 of RFC 4034
+        let types = vec![RecordType::A, RecordType::MX, RecordType::RRSIG, RecordType::NSEC, RecordType::UNKNOWN(1234)];
+        let body = DnsRecordBody::NSEC { next_domain: "host.example.com".parse().unwrap(), types };
+        assert_eq!(body.to_string(), "host.example.com A MX RRSIG NSEC TYPE1234");
+        let (_, buf) = roundtrip(RecordType::NSEC, body);
+        let mut bitmap = vec![0x00, 0x06, 0x40, 0x01, 0x00, 0x00, 0x00, 0x03, 0x04, 0x1B];
+        bitmap.extend_from_slice(&[0; 26]);
+        bitmap.push(0x20);
+        assert_eq!(&buf.as_bytes()[buf.len - bitmap.len()..], &bitmap[..]);
+    }
+
+    #[test]
+    fn test_nsec3_record() {
+        let body = DnsRecordBody::NSEC3 {
+            hash_algorithm: 1,
+            flags: 1,
+            iterations: 12,
+            salt: vec![0xAA, 0xBB, 0xCC, 0xDD],
+            next_hashed_owner: b"foobar".to_vec(),
+            types: vec![RecordType::NS, RecordType::DS, RecordType::RRSIG],
+        };
+        assert_eq!(body.to_string(), "1\t1\t12\tAABBCCDD\tCPNMUOJ1E8 NS DS RRSIG");
+        roundtrip(RecordType::NSEC3, body);
+
+        let body = DnsRecordBody::NSEC3PARAM { hash_algorithm: 1, flags: 0, iterations: 0, salt: Vec::new() };
+        assert_eq!(body.to_string(), "1\t0\t0\t-");
+        roundtrip(RecordType::NSEC3PARAM, body);
+    }
+
+    #[test]
+    fn test_svcb_record() {
+        // Figure 4 from appendix D.2 of RFC 9460
+        let rdata = [
+            0x00, 0x10,
+            0x03, b'f', b'o', b'o', 0x07, b'e', b'x', b'a', b'm', b'p', b'l', b'e', 0x03, b'o', b'r', b'g', 0x00,
+            0x00, 0x00, 0x00, 0x04, 0x00, 0x01, 0x00, 0x04,
+            0x00, 0x01, 0x00, 0x09, 0x02, b'h', b'2', 0x05, b'h', b'3', b'-', b'1', b'9',
+            0x00, 0x04, 0x00, 0x04, 0xC0, 0x00, 0x02, 0x01,
+        ];
+        let mut buf = DnsBuffer::from_bytes(&rdata);
+        let body = DnsRecord::new().body.read(&RecordType::SVCB, rdata.len() as u16, &mut buf).unwrap();
+        assert_eq!(body, DnsRecordBody::SVCB {
+            priority: 16,
+            target: "foo.example.org".parse().unwrap(),
+            params: vec![
+                SvcParam::Mandatory(vec![1, 4]),
+                SvcParam::Alpn(vec![b"h2".to_vec(), b"h3-19".to_vec()]),
+                SvcParam::Ipv4Hint(vec![Ipv4Addr::new(192, 0, 2, 1)]),
+            ],
+        });
+        assert_eq!(body.to_string(), "16\tfoo.example.org mandatory=alpn,ipv4hint alpn=h2,h3-19 ipv4hint=192.0.2.1");
+
+        let mut out = DnsBuffer::new();
+        body.write(&mut out).unwrap();
+        assert_eq!(out.as_bytes(), &rdata[..]);
+
+        // Keys must be in increasing order
+        let swapped = [&rdata[..19], &rdata[27..40], &rdata[19..27], &rdata[40..]].concat();
+        let mut buf = DnsBuffer::from_bytes(&swapped);
+        assert!(DnsRecord::new().body.read(&RecordType::SVCB, swapped.len() as u16, &mut buf).is_err());
+    }
+
+    #[test]
+    fn test_https_record() {
+        let body = DnsRecordBody::HTTPS {
+            priority: 1,
+            target: DnsName::root(),
+            params: vec![
+                SvcParam::Alpn(vec![b"f\\oo,bar".to_vec(), b"h2".to_vec()]),
+                SvcParam::NoDefaultAlpn,
+                SvcParam::Port(8443),
+                SvcParam::Ech(vec![0xFE, 0x0D]),
+                SvcParam::Ipv6Hint(vec!["2001:db8::1".parse().unwrap()]),
+                SvcParam::Unknown { key: 667, value: b"hello".to_vec() },
+            ],
+        };
+        let (record, _) = roundtrip(RecordType::HTTPS, body);
+        assert_eq!(record.body.to_string(), "1\t. alpn=f\\\\oo\\,bar,h2 no-default-alpn port=8443 ech=/g0= \
+                                             ipv6hint=2001:db8::1 key667=\"hello\"");
+        assert_eq!("HTTPS".parse::<RecordType>().unwrap(), RecordType::HTTPS);
+        assert_eq!(RecordType::from_num(64), RecordType::SVCB);
+    }
+
+    #[test]
+    fn test_certificate_records() {
+        let body = DnsRecordBody::TLSA { usage: 3, selector: 1, matching_type: 1, data: vec![0xAB, 0xCD, 0xEF] };
+        let (record, _) = roundtrip(RecordType::TLSA, body);
+        assert_eq!(record.body.to_string(), "3\t1\t1\tABCDEF");
+
+        let body = DnsRecordBody::SMIMEA { usage: 0, selector: 0, matching_type: 2, data: vec![0x01] };
+        let (record, _) = roundtrip(RecordType::SMIMEA, body);
+        assert_eq!(record.body.to_string(), "0\t0\t2\t01");
+
+        let body = DnsRecordBody::SSHFP { algorithm: 4, fingerprint_type: 2, fingerprint: vec![0x12, 0x34] };
+        let (record, _) = roundtrip(RecordType::SSHFP, body);
+        assert_eq!(record.body.to_string(), "4\t2\t1234");
+
+        let body = DnsRecordBody::OPENPGPKEY { public_key: b"foobar".to_vec() };
+        let (record, _) = roundtrip(RecordType::OPENPGPKEY, body);
+        assert_eq!(record.body.to_string(), "Zm9vYmFy");
+    }
+
+    #[test]
+    fn test_internationalized_query() {
+        let packet = DnsPacket::from_query("Bücher.de", "MX").unwrap();
+        assert_eq!(packet.questions[0].name, "xn--bcher-kva.de");
+        assert!(DnsPacket::from_query("bad\u{200D}.de", "A").is_err());
+
+        let mut buf = DnsBuffer::new();
+        packet.write(&mut buf).unwrap();
+        assert_eq!(buf.get_range(12, 14).unwrap(), b"\x0dxn--bcher-kva");
+
+        let mut record = DnsRecord::new();
+        record.preamble.name = "xn--bcher-kva.de".parse().unwrap();
+        record.preamble.record_type = RecordType::MX;
+        record.preamble.record_class = RecordClass::IN;
+        record.body = DnsRecordBody::MX { priority: 10, name: "mail.xn--bcher-kva.de".parse().unwrap() };
+        assert_eq!(record.to_string(), "xn--bcher-kva.de\tMX\tIN\t0\t0\tmail.xn--bcher-kva.de\t10");
+        assert_eq!(format!("{:#}", record), "bücher.de\tMX\tIN\t0\t0\tmail.bücher.de\t10");
+        assert_eq!(format!("{:#}", packet.questions[0]), "bücher.de\tMX\tIN");
+
+        // Every name in the record data is converted, not just the first
+        let soa = DnsRecordBody::SOA {
+            mname: "ns.xn--bcher-kva.de".parse().unwrap(),
+            rname: "hostmaster.xn--bcher-kva.de".parse().unwrap(),
+            serial: 1, refresh: 7200, retry: 3600, expire: 1209600, minimum: 300,
+        };
+        assert_eq!(format!("{:#}", soa), "ns.bücher.de\thostmaster.bücher.de\t1\t7200\t3600\t1209600\t300");
+        let nsec = DnsRecordBody::NSEC { next_domain: "www.xn--bcher-kva.de".parse().unwrap(), types: vec![RecordType::A] };
+        assert_eq!(format!("{:#}", nsec), "www.bücher.de A");
+    }
+
+    #[test]
+    fn test_record_class() {
+        for (num, name) in [(1, "IN"), (3, "CH"), (4, "HS"), (254, "NONE"), (255, "ANY"), (15, "CLASS15"), (0, "CLASS0")].iter() {
+            let class = RecordClass::from_num(*num);
+            assert_eq!(class.to_num(), *num);
+            assert_eq!(class.to_string(), *name);
+            assert_eq!(name.parse::<RecordClass>().unwrap(), class);
+        }
+        assert_eq!("CHAOS".parse::<RecordClass>().unwrap(), RecordClass::CH);
+        assert!("CLASSX".parse::<RecordClass>().is_err());
+        assert!("MX".parse::<RecordClass>().is_err());
+
+        // Unassigned classes must survive being forwarded
+        let packet = DnsPacket::from_query_class("version.bind", "TXT", "CLASS1234").unwrap();
+        let mut buf = DnsBuffer::new();
+        packet.write(&mut buf).unwrap();
+        buf.pos = 0;
+        let mut new_packet = DnsPacket::new();
+        new_packet.read(&mut buf).unwrap();
+        assert_eq!(new_packet.questions[0].record_class, RecordClass::UNKNOWN(1234));
+        assert_eq!(new_packet.questions[0].to_string(), "version.bind\tTXT\tCLASS1234");
+    }
+
+    #[test]
+    fn test_response_codes() {
+        // A REFUSED answer used to be read as NOERROR
+        let mut bytes = [0x12, 0x34, 0x81, 0x85, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        let mut packet = DnsPacket::new();
+        packet.read(&mut DnsBuffer::from_bytes(&bytes)).unwrap();
+        assert_eq!(packet.header.response_code, ResponseCode::REFUSED);
+
+        // Unassigned codes keep their number
+        bytes[3] = 0x8C;
+        let mut packet = DnsPacket::new();
+        packet.read(&mut DnsBuffer::from_bytes(&bytes)).unwrap();
+        assert_eq!(packet.header.response_code, ResponseCode::UNKNOWN(12));
+        assert_eq!(packet.header.response_code.to_string(), "RCODE12");
+        let mut out = DnsBuffer::new();
+        packet.write(&mut out).unwrap();
+        assert_eq!(out.as_bytes(), &bytes[..]);
+
+        for num in 0..24 {
+            assert_eq!(ResponseCode::from_num(num).to_num(), num);
+        }
+        assert_eq!(ResponseCode::from_num(8).to_string(), "NXRRSET");
+    }
+
+    #[test]
+    fn test_opcode_range() {
+        let mut header = DnsHeader::new();
+        header.opcode = Opcode::UNKNOWN(15);
+        let mut buf = DnsBuffer::new();
+        header.write(&mut buf).unwrap();
+        buf.pos = 0;
+        let mut read = DnsHeader::new();
+        read.read(&mut buf).unwrap();
+        assert_eq!(read.opcode, Opcode::UNKNOWN(15));
+
+        header.opcode = Opcode::UNKNOWN(16);
+        let mut buf = DnsBuffer::new();
+        assert!(matches!(header.write(&mut buf), Err(DnsError::InvalidInput(_))));
+        assert_eq!(buf.pos, 0);
+    }
+
+    #[test]
+    fn test_extended_response_code() {
+        let mut packet = DnsPacket::new();
+        packet.header.query_response = true;
+        packet.header.response_code = ResponseCode::BADCOOKIE;
+        packet.add_edns(1232);
+
+        let mut buf = DnsBuffer::new();
+        packet.write(&mut buf).unwrap();
+        // BADCOOKIE is 23, so 7 in the header and 1 in the OPT record
+        assert_eq!(buf.get(3).unwrap() & 0x0F, 7);
+        assert_eq!(buf.get(17).unwrap(), 1);
+
+        buf.pos = 0;
+        let mut new_packet = DnsPacket::new();
+        new_packet.read(&mut buf).unwrap();
+        assert_eq!(new_packet.header.response_code, ResponseCode::BADCOOKIE);
+        assert_eq!(new_packet.edns.unwrap().extended_rcode, 1);
+
+        // BADCOOKIE would go out as NOERROR without the OPT record
+        packet.remove_edns();
+        assert!(matches!(packet.write(&mut DnsBuffer::new()), Err(DnsError::InvalidInput(_))));
+    }
+}
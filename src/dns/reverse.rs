@@ -0,0 +1,102 @@
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+use crate::error::{DnsError, Result};
+
+/// Builds the in-addr.arpa name used to look up the PTR record for an
+/// address, such as 4.4.8.8.in-addr.arpa for 8.8.4.4.
+pub fn ipv4_reverse_name(addr: Ipv4Addr) -> String {
+    let octets = addr.octets();
+    format!("{}.{}.{}.{}.in-addr.arpa", octets[3], octets[2], octets[1], octets[0])
+}
+
+/// Builds the ip6.arpa name used to look up the PTR record for an address,
+/// with one label per nibble in reverse order.  See section 2.5 of
+/// https://tools.ietf.org/html/rfc3596 for details.
+pub fn ipv6_reverse_name(addr: Ipv6Addr) -> String {
+    let mut name = String::new();
+    for byte in addr.octets().iter().rev() {
+        name.push_str(&format!("{:x}.{:x}.", byte & 0x0F, byte >> 4));
+    }
+    name.push_str("ip6.arpa");
+    name
+}
+
+pub fn reverse_name(addr: IpAddr) -> String {
+    match addr {
+        IpAddr::V4(addr) => ipv4_reverse_name(addr),
+        IpAddr::V6(addr) => ipv6_reverse_name(addr),
+    }
+}
+
+/// Turns an in-addr.arpa or ip6.arpa name back into the address it describes.
+pub fn parse_reverse_name(name: &str) -> Result<IpAddr> {
+    let invalid = || DnsError::InvalidInput(format!("Not a reverse lookup name: {}", name));
+    let lower = name.trim_end_matches('.').to_ascii_lowercase();
+
+    if let Some(labels) = lower.strip_suffix(".in-addr.arpa") {
+        let mut octets = [0_u8; 4];
+        let parts: Vec<&str> = labels.split('.').collect();
+        if parts.len() != 4 {
+            return Err(invalid());
+        }
+        for (idx, part) in parts.iter().rev().enumerate() {
+            // Leading zeros would give a second name for the same address
+            if part.len() > 1 && part.starts_with('0') {
+                return Err(invalid());
+            }
+            octets[idx] = part.parse().map_err(|_| invalid())?;
+        }
+        Ok(IpAddr::V4(Ipv4Addr::from(octets)))
+    } else if let Some(labels) = lower.strip_suffix(".ip6.arpa") {
+        let mut octets = [0_u8; 16];
+        let parts: Vec<&str> = labels.split('.').collect();
+        if parts.len() != 32 {
+            return Err(invalid());
+        }
+        for (idx, part) in parts.iter().rev().enumerate() {
+            if part.len() != 1 {
+                return Err(invalid());
+            }
+            let nibble = u8::from_str_radix(part, 16).map_err(|_| invalid())?;
+            if idx % 2 == 0 {
+                octets[idx / 2] = nibble << 4;
+            } else {
+                octets[idx / 2] |= nibble;
+            }
+        }
+        Ok(IpAddr::V6(Ipv6Addr::from(octets)))
+    } else {
+        Err(invalid())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ipv4() {
+        let addr = Ipv4Addr::new(192, 0, 2, 10);
+        assert_eq!(ipv4_reverse_name(addr), "10.2.0.192.in-addr.arpa");
+        assert_eq!(parse_reverse_name("10.2.0.192.in-addr.arpa.").unwrap(), IpAddr::V4(addr));
+        assert_eq!(parse_reverse_name("10.2.0.192.IN-ADDR.ARPA").unwrap(), IpAddr::V4(addr));
+
+        assert!(parse_reverse_name("2.0.192.in-addr.arpa").is_err());
+        assert!(parse_reverse_name("256.2.0.192.in-addr.arpa").is_err());
+        assert!(parse_reverse_name("010.2.0.192.in-addr.arpa").is_err());
+        assert!(parse_reverse_name("google.com").is_err());
+    }
+
+    #[test]
+    fn test_ipv6() {
+        let addr: Ipv6Addr = "2001:db8::567:89ab".parse().unwrap();
+        let name = "b.a.9.8.7.6.5.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.0.8.b.d.0.1.0.0.2.ip6.arpa";
+        assert_eq!(ipv6_reverse_name(addr), name);
+        assert_eq!(reverse_name(IpAddr::V6(addr)), name);
+        assert_eq!(parse_reverse_name(name).unwrap(), IpAddr::V6(addr));
+
+        assert!(parse_reverse_name("b.a.9.8.ip6.arpa").is_err());
+        assert!(parse_reverse_name(&name.replace("b.a", "ba.0")).is_err());
+        assert!(parse_reverse_name(&name.replacen('b', "g", 1)).is_err());
+    }
+}
@@ -1,49 +1,249 @@
-
-use std::io::Result;
 use std::net::UdpSocket;
 
-use crate::buffer::DnsBuffer;
-use crate::packet::{DnsPacket, ResponseCode};
+use crate::error::{DnsError, Result};
+use crate::buffer::{DnsBuffer, EDNS_UDP_SIZE};
+use crate::builder::DnsPacketBuilder;
+use crate::packet::{DnsPacket, Opcode, ResponseCode};
 use crate::client::DnsClient;
 
+/// Answers a message the server doesn't forward upstream, such as a NOTIFY
+/// from a primary.  The server fills in the id, opcode and QR bit of the
+/// response it returns, and answers with SERVFAIL if it returns an error.
+pub type MessageHandler = Box<dyn Fn(&DnsPacket) -> Result<DnsPacket>>;
+
 pub struct DnsServer {
     pub socket: UdpSocket,
+    // Called for NOTIFY messages, see https://tools.ietf.org/html/rfc1996
+    pub notify_handler: Option<MessageHandler>,
+    // Called for UPDATE messages, see https://tools.ietf.org/html/rfc2136
+    pub update_handler: Option<MessageHandler>,
 }
 
 impl DnsServer {
     pub fn new() -> Result<DnsServer> {
         Ok(DnsServer {
             socket: UdpSocket::bind("0.0.0.0:10053")?,
+            notify_handler: None,
+            update_handler: None,
         })
     }
 
     pub fn talk(&self) -> Result<DnsPacket> {
-        let mut query_buffer = DnsBuffer::new();
-        let (_, src) = self.socket.recv_from(&mut query_buffer.buf)?;
+        let mut query_buffer = DnsBuffer::with_capacity(EDNS_UDP_SIZE);
+        let (bytes_read, src) = self.socket.recv_from(&mut query_buffer.buf)?;
+        query_buffer.set_len(bytes_read)?;
         let mut query_packet = DnsPacket::new();
 
         query_packet.read(&mut query_buffer)?;
 
-        let mut response_packet = DnsPacket::new();
+        let mut response_packet = self.respond(&query_packet)?;
+        let mut response_buffer = DnsBuffer::with_capacity(query_packet.max_udp_size());
+        match response_packet.write(&mut response_buffer) {
+            Ok(()) => {},
+            // The response is too large for the client, so send back only the
+            // question and let it retry over TCP.
+            Err(DnsError::BufferFull { .. }) => {
+                response_packet.header.truncated_message = true;
+                response_packet.answers.clear();
+                response_packet.authorities.clear();
+                response_packet.additional.clear();
+                response_packet.update_counts();
+                response_buffer = DnsBuffer::with_capacity(query_packet.max_udp_size());
+                response_packet.write(&mut response_buffer)?;
+            },
+            // Anything else can't be sent at all, so let the client know
+            // rather than leaving it to time out
+            Err(_) => {
+                response_packet = DnsPacketBuilder::response(&query_packet).response_code(ResponseCode::SERVFAIL).build();
+                response_buffer = DnsBuffer::with_capacity(query_packet.max_udp_size());
+                response_packet.write(&mut response_buffer)?;
+            },
+        }
+        self.socket.send_to(&response_buffer.buf[0..response_buffer.pos], src)?;
+        Ok(response_packet)
+    }
+
+    /// Builds the response to a message, dispatching on its opcode.  Queries
+    /// are forwarded upstream, NOTIFY and UPDATE go to their handlers, and
+    /// anything else is answered with NOTIMP.
+    pub fn respond(&self, query_packet: &DnsPacket) -> Result<DnsPacket> {
+        let handler = match query_packet.header.opcode {
+            Opcode::QUERY => return self.resolve(query_packet),
+            Opcode::NOTIFY => self.notify_handler.as_ref(),
+            Opcode::UPDATE => self.update_handler.as_ref(),
+            _ => None,
+        };
+
+        let mut response_packet = match handler {
+            Some(handler) => handler(query_packet).unwrap_or_else(|_| {
+                DnsPacketBuilder::response(query_packet).response_code(ResponseCode::SERVFAIL).build()
+            }),
+            None => DnsPacketBuilder::response(query_packet).response_code(ResponseCode::NOTIMP).build(),
+        };
         response_packet.header.id = query_packet.header.id;
-        response_packet.header.recursion_desired = true;
+        response_packet.header.opcode = query_packet.header.opcode;
+        response_packet.header.query_response = true;
+        Ok(response_packet)
+    }
+
+    fn resolve(&self, query_packet: &DnsPacket) -> Result<DnsPacket> {
+        let response = DnsPacketBuilder::response(query_packet).recursion_available(true);
 
         if query_packet.questions.is_empty() {
-            response_packet.header.response_code = ResponseCode::FORMERR;
-        } else {
-            let client = DnsClient::new()?;
-            let mut buf = DnsBuffer::new();
-            response_packet.header.question_count = 1;
-            response_packet.questions.push(query_packet.questions[0].clone());
-            response_packet.write(&mut buf)?;
-            response_packet = client.query(&buf)?;
+            return Ok(response.response_code(ResponseCode::FORMERR).build());
+        }
+        if query_packet.edns.as_ref().is_some_and(|edns| edns.version > 0) {
+            // Only EDNS version 0 exists, so anything else gets BADVERS, whose
+            // upper bits live in the OPT record.
+            return Ok(response.edns(EDNS_UDP_SIZE as u16).response_code(ResponseCode::BADVERS).build());
+        }
+
+        let client = DnsClient::new()?;
+        let mut buf = DnsBuffer::new();
+        let mut upstream_query = DnsPacketBuilder::query()
+            .question(query_packet.questions[0].clone())
+            .edns(client.udp_payload_size)
+            .build();
+        if let (Some(query_edns), Some(edns)) = (&query_packet.edns, &mut upstream_query.edns) {
+            edns.dnssec_ok = query_edns.dnssec_ok;
+        }
+        upstream_query.write(&mut buf)?;
+        let mut response_packet = client.query(&buf)?;
+
+        // The upstream query had an id of its own
+        response_packet.header.id = query_packet.header.id;
+        // Options from upstream are meant for us, not for our client
+        match &mut response_packet.edns {
+            Some(edns) if query_packet.edns.is_some() => {
+                edns.udp_payload_size = EDNS_UDP_SIZE as u16;
+                edns.options.clear();
+            },
+            _ => response_packet.remove_edns(),
         }
 
         response_packet.header.recursion_available = true;
         response_packet.header.query_response = true;
-        let mut response_buffer = DnsBuffer::new();
-        response_packet.write(&mut response_buffer)?;
-        self.socket.send_to(&response_buffer.buf[0..response_buffer.pos], src)?;
         Ok(response_packet)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::packet::DnsRecord;
+
+    fn server() -> DnsServer {
+        DnsServer {
+            socket: UdpSocket::bind("127.0.0.1:0").unwrap(),
+            notify_handler: None,
+            update_handler: None,
+        }
+    }
+
+    fn send(server: &DnsServer, packet: &DnsPacket) -> UdpSocket {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let mut buf = DnsBuffer::new();
+        packet.write(&mut buf).unwrap();
+        socket.send_to(buf.as_bytes(), server.socket.local_addr().unwrap()).unwrap();
+        socket
+    }
+
+    fn message(opcode: Opcode) -> DnsPacket {
+        let mut packet = DnsPacket::from_query("example.com", "SOA").unwrap();
+        packet.header.id = 4321;
+        packet.header.opcode = opcode;
+        packet
+    }
+
+    #[test]
+    fn test_unimplemented_opcodes() {
+        let server = server();
+        for opcode in [Opcode::IQUERY, Opcode::STATUS, Opcode::NOTIFY, Opcode::UPDATE, Opcode::DSO, Opcode::UNKNOWN(15)] {
+            let response = server.respond(&message(opcode)).unwrap();
+            assert_eq!(response.header.response_code, ResponseCode::NOTIMP);
+            assert_eq!(response.header.opcode, opcode);
+            assert_eq!(response.header.id, 4321);
+            assert!(response.header.query_response);
+        }
+    }
+
+    #[test]
+    fn test_notify_handler() {
+        let mut server = server();
+        server.notify_handler = Some(Box::new(|notify: &DnsPacket| {
+            Ok(DnsPacketBuilder::response(notify).authoritative_answer(true).build())
+        }));
+
+        let response = server.respond(&message(Opcode::NOTIFY)).unwrap();
+        assert_eq!(response.header.response_code, ResponseCode::NOERROR);
+        assert_eq!(response.header.opcode, Opcode::NOTIFY);
+        assert_eq!(response.header.id, 4321);
+        assert!(response.header.authoritative_answer);
+        assert_eq!(response.questions.len(), 1);
+
+        // UPDATE has no handler of its own
+        let response = server.respond(&message(Opcode::UPDATE)).unwrap();
+        assert_eq!(response.header.response_code, ResponseCode::NOTIMP);
+    }
+
+    #[test]
+    fn test_handler_error() {
+        let mut server = server();
+        server.update_handler = Some(Box::new(|_: &DnsPacket| {
+            Err(DnsError::InvalidInput(String::from("Zone is read only")))
+        }));
+
+        let response = server.respond(&message(Opcode::UPDATE)).unwrap();
+        assert_eq!(response.header.response_code, ResponseCode::SERVFAIL);
+        assert_eq!(response.header.opcode, Opcode::UPDATE);
+        assert_eq!(response.header.id, 4321);
+        assert!(response.header.query_response);
+    }
+
+    #[test]
+    fn test_talk_truncation() {
+        let mut server = server();
+        server.notify_handler = Some(Box::new(|notify: &DnsPacket| {
+            let address = "192.0.2.1".parse().unwrap();
+            Ok((0..100).fold(DnsPacketBuilder::response(notify), |response, _| {
+                response.answer(DnsRecord::a("example.com".parse().unwrap(), 300, address))
+            }).build())
+        }));
+        let client = send(&server, &message(Opcode::NOTIFY));
+        let response = server.talk().unwrap();
+        assert!(response.header.truncated_message);
+        assert!(response.answers.is_empty());
+
+        let mut buf = DnsBuffer::new();
+        let (size, _) = client.recv_from(&mut buf.buf).unwrap();
+        buf.set_len(size).unwrap();
+        let mut packet = DnsPacket::new();
+        packet.read(&mut buf).unwrap();
+        assert!(packet.header.truncated_message);
+        assert_eq!(packet.header.answer_count, 0);
+    }
+
+    #[test]
+    fn test_talk_write_error() {
+        // A response that can't be written is replaced with SERVFAIL
+        let mut server = server();
+        server.notify_handler = Some(Box::new(|notify: &DnsPacket| {
+            let mut response = DnsPacketBuilder::response(notify).response_code(ResponseCode::BADCOOKIE).build();
+            response.remove_edns();
+            Ok(response)
+        }));
+        let client = send(&server, &message(Opcode::NOTIFY));
+        let response = server.talk().unwrap();
+        assert_eq!(response.header.response_code, ResponseCode::SERVFAIL);
+
+        let mut buf = DnsBuffer::new();
+        let (size, _) = client.recv_from(&mut buf.buf).unwrap();
+        buf.set_len(size).unwrap();
+        let mut packet = DnsPacket::new();
+        packet.read(&mut buf).unwrap();
+        assert_eq!(packet.header.response_code, ResponseCode::SERVFAIL);
+        assert_eq!(packet.header.id, 4321);
+        assert_eq!(packet.header.opcode, Opcode::NOTIFY);
+        assert_eq!(packet.questions, message(Opcode::NOTIFY).questions);
+    }
+}
@@ -0,0 +1,125 @@
+use crate::name::DnsName;
+use crate::packet::{DnsRecord, DnsRecordBody};
+use crate::random::random_u64;
+
+/// Orders the SRV records in `records` the way a client should try them, as
+/// described in the "Usage rules" of https://tools.ietf.org/html/rfc2782.
+/// Lower priorities come first, and targets sharing a priority are picked at
+/// random in proportion to their weight.  Returns host and port pairs ready to
+/// be handed to `TcpStream::connect`.
+pub fn order_srv_targets(records: &[DnsRecord]) -> Vec<(String, u16)> {
+    order_srv_targets_with(records, |total| (random_u64() % (total as u64 + 1)) as u32)
+}
+
+/// Same as `order_srv_targets`, but with the random number source supplied
+/// by the caller.  `pick` is given the total weight left in a priority
+/// group and must return a number between 0 and that total, inclusive.
+pub fn order_srv_targets_with<F>(records: &[DnsRecord], mut pick: F) -> Vec<(String, u16)>
+    where F: FnMut(u32) -> u32 {
+    let mut targets: Vec<(u16, u16, u16, &DnsName)> = records.iter()
+        .filter_map(|record| match &record.body {
+            DnsRecordBody::SRV { priority, weight, port, target } => Some((*priority, *weight, *port, target)),
+            _ => None,
+        })
+        .collect();
+
+    // A lone "." target means the service is decidedly not available
+    if targets.len() == 1 && targets[0].3.is_root() {
+        return Vec::new();
+    }
+
+    // Zero weight targets go first within their priority, so they only get
+    // picked when the random number lands on zero.
+    targets.sort_by_key(|&(priority, weight, _, _)| (priority, weight != 0));
+
+    let mut ordered = Vec::with_capacity(targets.len());
+    while !targets.is_empty() {
+        let priority = targets[0].0;
+        let group_len = targets.iter().take_while(|target| target.0 == priority).count();
+        let mut group: Vec<_> = targets.drain(..group_len).collect();
+
+        while !group.is_empty() {
+            let total: u32 = group.iter().map(|target| target.1 as u32).sum();
+            let chosen = pick(total);
+            let mut running_sum = 0;
+            let idx = group.iter()
+                .position(|target| {
+                    running_sum += target.1 as u32;
+                    running_sum >= chosen
+                })
+                .unwrap_or(group.len() - 1);
+
+            let (_, _, port, target) = group.remove(idx);
+            ordered.push((target.to_string(), port));
+        }
+    }
+    ordered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::buffer::DnsBuffer;
+
+    fn srv_record(priority: u16, weight: u16, port: u16, target: &str) -> DnsRecord {
+        let mut record = DnsRecord::new();
+        record.body = DnsRecordBody::SRV { priority, weight, port, target: target.parse().unwrap() };
+        record
+    }
+
+    #[test]
+    fn test_priority_order() {
+        let records = vec![
+            srv_record(20, 0, 5060, "backup.example.com"),
+            srv_record(10, 0, 5061, "primary.example.com"),
+        ];
+        let ordered = order_srv_targets(&records);
+        assert_eq!(ordered, vec![
+            (String::from("primary.example.com"), 5061),
+            (String::from("backup.example.com"), 5060),
+        ]);
+    }
+
+    #[test]
+    fn test_weighted_selection() {
+        let records = vec![
+            srv_record(10, 60, 80, "a.example.com"),
+            srv_record(10, 0, 80, "zero.example.com"),
+            srv_record(10, 40, 80, "b.example.com"),
+        ];
+
+        // Running sums are zero=0, a=60, b=100, so 61 lands on b, then 0 on zero
+        let mut picks = vec![61, 0, 60].into_iter();
+        let ordered = order_srv_targets_with(&records, |_| picks.next().unwrap());
+        let hosts: Vec<&str> = ordered.iter().map(|(host, _)| &host[..]).collect();
+        assert_eq!(hosts, vec!["b.example.com", "zero.example.com", "a.example.com"]);
+
+        // Every target is returned exactly once whatever the random numbers
+        let ordered = order_srv_targets(&records);
+        assert_eq!(ordered.len(), 3);
+    }
+
+    #[test]
+    fn test_unavailable() {
+        assert!(order_srv_targets(&[srv_record(0, 0, 0, ".")]).is_empty());
+        assert!(order_srv_targets(&[srv_record(0, 0, 0, "")]).is_empty());
+    }
+
+    #[test]
+    fn test_write_srv() {
+        let mut record = srv_record(10, 5, 5060, "sip.example.com");
+        record.preamble.name = "_sip._udp.example.com".parse().unwrap();
+        record.preamble.record_type = crate::packet::RecordType::SRV;
+
+        let mut buf = DnsBuffer::new();
+        record.write(&mut buf).unwrap();
+        // The target is written in full even though example.com could be compressed
+        assert_eq!(buf.pos, 23 + 10 + 6 + 17);
+        buf.pos = 0;
+
+        let mut new_record = DnsRecord::new();
+        new_record.read(&mut buf).unwrap();
+        assert_eq!(new_record.body, record.body);
+        assert_eq!(new_record.body.to_string(), "10\t5\t5060\tsip.example.com");
+    }
+}
@@ -0,0 +1,79 @@
+use ring::digest;
+
+use crate::error::{DnsError, Result};
+use crate::packet::{DnsRecord, DnsRecordBody};
+
+/// The SSHFP algorithm number for an SSH public key, taken from the key type
+/// at the start of its wire format blob.  See the registry at
+/// https://www.iana.org/assignments/dns-sshfp-rr-parameters
+pub fn sshfp_algorithm(host_key: &[u8]) -> Result<u8> {
+    let invalid = || DnsError::InvalidInput(String::from("Malformed SSH public key"));
+    if host_key.len() < 4 {
+        return Err(invalid());
+    }
+    let len = u32::from_be_bytes([host_key[0], host_key[1], host_key[2], host_key[3]]) as usize;
+    let key_type = host_key.get(4..4 + len).ok_or_else(invalid)?;
+
+    match key_type {
+        b"ssh-rsa" => Ok(1),
+        b"ssh-dss" => Ok(2),
+        b"ecdsa-sha2-nistp256" | b"ecdsa-sha2-nistp384" | b"ecdsa-sha2-nistp521" => Ok(3),
+        b"ssh-ed25519" => Ok(4),
+        b"ssh-ed448" => Ok(6),
+        _ => Err(DnsError::InvalidInput(format!("Unsupported SSH key type {}", String::from_utf8_lossy(key_type)))),
+    }
+}
+
+/// Checks an SSH host key, in the wire format used by known_hosts files once
+/// base64 decoded, against the SSHFP records for the host.  Returns true if
+/// any record of the key's algorithm has a matching SHA-1 or SHA-256
+/// fingerprint, as described in https://tools.ietf.org/html/rfc4255 and
+/// https://tools.ietf.org/html/rfc6594.  The records should come from a
+/// response DNSSEC validated as secure, or they prove nothing.
+pub fn verify_sshfp(host_key: &[u8], records: &[DnsRecord]) -> Result<bool> {
+    let key_algorithm = sshfp_algorithm(host_key)?;
+
+    Ok(records.iter().any(|record| match &record.body {
+        DnsRecordBody::SSHFP { algorithm, fingerprint_type, fingerprint } if *algorithm == key_algorithm => {
+            let digest_algorithm = match fingerprint_type {
+                1 => &digest::SHA1_FOR_LEGACY_USE_ONLY,
+                2 => &digest::SHA256,
+                _ => return false,
+            };
+            digest::digest(digest_algorithm, host_key).as_ref() == &fingerprint[..]
+        },
+        _ => false,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::encoding::{base64_decode, hex_decode};
+
+    fn sshfp_record(algorithm: u8, fingerprint_type: u8, fingerprint: &str) -> DnsRecord {
+        let mut record = DnsRecord::new();
+        record.body = DnsRecordBody::SSHFP { algorithm, fingerprint_type, fingerprint: hex_decode(fingerprint).unwrap() };
+        record
+    }
+
+    #[test]
+    fn test_verify_sshfp() {
+        // Ed25519 key from section 3 of https://tools.ietf.org/html/rfc7479
+        let host_key = base64_decode("AAAAC3NzaC1lZDI1NTE5AAAAIGPKSUTyz1HwHReFVvD5obVsALAgJRNarH4TRpNePnAS").unwrap();
+        assert_eq!(sshfp_algorithm(&host_key).unwrap(), 4);
+
+        let sha1 = sshfp_record(4, 1, "06A2DE9A2D0F034701D67917E49CFC45A03C2E61");
+        let sha256 = sshfp_record(4, 2, "A87F1B687AC0E57D2A081A2F282672334D90ED316D2B818CA9580EA384D92401");
+        let other = sshfp_record(4, 2, "0000000000000000000000000000000000000000000000000000000000000000");
+        let wrong_algorithm = sshfp_record(1, 2, "A87F1B687AC0E57D2A081A2F282672334D90ED316D2B818CA9580EA384D92401");
+
+        assert!(verify_sshfp(&host_key, &[sha1]).unwrap());
+        assert!(verify_sshfp(&host_key, &[other.clone(), sha256]).unwrap());
+        assert!(!verify_sshfp(&host_key, &[other, wrong_algorithm]).unwrap());
+        assert!(!verify_sshfp(&host_key, &[]).unwrap());
+
+        assert!(verify_sshfp(&host_key[..10], &[]).is_err());
+        assert!(verify_sshfp(b"\x00\x00\x00\x07ssh-foo", &[]).is_err());
+    }
+}
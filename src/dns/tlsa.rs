@@ -0,0 +1,187 @@
+use ring::digest;
+
+use crate::error::{DnsError, Result};
+use crate::packet::{DnsRecord, DnsRecordBody};
+
+// Certificate usages, see section 2.1.1 of https://tools.ietf.org/html/rfc6698
+const PKIX_TA: u8 = 0;
+const PKIX_EE: u8 = 1;
+const DANE_TA: u8 = 2;
+const DANE_EE: u8 = 3;
+
+// DER tags needed to find the public key in a certificate
+const SEQUENCE: u8 = 0x30;
+const EXPLICIT_VERSION: u8 = 0xA0;
+
+/// Checks a TLS certificate chain against TLSA (or SMIMEA) records.  `chain`
+/// holds DER encoded certificates, starting with the server's own and
+/// followed by the issuers it presented.  Returns true if any record matches
+/// the certificate its usage refers to: the server's certificate for usages 1
+/// and 3, or one of the issuers for usages 0 and 2.
+///
+/// Usages 0 and 1 also require the chain to pass normal PKIX validation, which
+/// is left to the TLS library.  See https://tools.ietf.org/html/rfc6698 and
+/// https://tools.ietf.org/html/rfc7671 for details.
+pub fn verify_tlsa(chain: &[&[u8]], records: &[DnsRecord]) -> Result<bool> {
+    if chain.is_empty() {
+        return Err(DnsError::InvalidInput(String::from("Empty certificate chain")));
+    }
+
+    for record in records {
+        let (usage, selector, matching_type, data) = match &record.body {
+            DnsRecordBody::TLSA { usage, selector, matching_type, data } |
+            DnsRecordBody::SMIMEA { usage, selector, matching_type, data } => (*usage, *selector, *matching_type, data),
+            _ => continue,
+        };
+        let candidates = match usage {
+            PKIX_EE | DANE_EE => &chain[..1],
+            PKIX_TA | DANE_TA => &chain[1..],
+            _ => continue,
+        };
+        for cert in candidates {
+            if tlsa_matches(cert, selector, matching_type, data)? {
+                return Ok(true);
+            }
+        }
+    }
+    Ok(false)
+}
+
+/// Checks a single DER encoded certificate against the association data of
+/// a TLSA record, using its selector and matching type.
+pub fn tlsa_matches(cert: &[u8], selector: u8, matching_type: u8, data: &[u8]) -> Result<bool> {
+    let selected = match selector {
+        0 => cert,
+        1 => subject_public_key_info(cert)?,
+        _ => return Ok(false),
+    };
+    let matched = match matching_type {
+        0 => selected == data,
+        1 => digest::digest(&digest::SHA256, selected).as_ref() == data,
+        2 => digest::digest(&digest::SHA512, selected).as_ref() == data,
+        _ => false,
+    };
+    Ok(matched)
+}
+
+/// Finds the DER encoded SubjectPublicKeyInfo in a certificate, the seventh
+/// field of the TBSCertificate, or the sixth without the optional version.
+/// See section 4.1 of https://tools.ietf.org/html/rfc5280
+pub fn subject_public_key_info(cert: &[u8]) -> Result<&[u8]> {
+    let invalid = || DnsError::InvalidInput(String::from("Malformed certificate"));
+
+    let (tag, certificate, _) = read_der(cert).ok_or_else(invalid)?;
+    if tag != SEQUENCE {
+        return Err(invalid());
+    }
+    let (tag, tbs, _) = read_der(certificate).ok_or_else(invalid)?;
+    if tag != SEQUENCE {
+        return Err(invalid());
+    }
+
+    let mut rest = tbs;
+    let (tag, _, after) = read_der(rest).ok_or_else(invalid)?;
+    if tag == EXPLICIT_VERSION {
+        rest = after;
+    }
+    // Skip the serial number, signature algorithm, issuer, validity and subject
+    for _ in 0..5 {
+        let (_, _, after) = read_der(rest).ok_or_else(invalid)?;
+        rest = after;
+    }
+
+    let (tag, _, after) = read_der(rest).ok_or_else(invalid)?;
+    if tag != SEQUENCE {
+        return Err(invalid());
+    }
+    Ok(&rest[..rest.len() - after.len()])
+}
+
+/// Reads one DER element, returning its tag, its contents and whatever
+/// follows it.
+fn read_der(data: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let tag = *data.first()?;
+    let first = *data.get(1)? as usize;
+    let (len, header) = if first & 0x80 == 0 {
+        (first, 2)
+    } else {
+        // Long form, where the low bits give the number of length bytes
+        let count = first & 0x7F;
+        if count == 0 || count > 4 {
+            return None;
+        }
+        let len = data.get(2..2 + count)?.iter().fold(0_usize, |len, &byte| len << 8 | byte as usize);
+        (len, 2 + count)
+    };
+    let contents = data.get(header..header.checked_add(len)?)?;
+    Some((tag, contents, &data[header + len..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn der(tag: u8, contents: &[u8]) -> Vec<u8> {
+        let mut out = vec![tag];
+        if contents.len() < 0x80 {
+            out.push(contents.len() as u8);
+        } else {
+            out.extend_from_slice(&[0x82, (contents.len() >> 8) as u8, contents.len() as u8]);
+        }
+        out.extend_from_slice(contents);
+        out
+    }
+
+    // A certificate with the right shape, though nothing in it is real
+    fn certificate(public_key: &[u8]) -> (Vec<u8>, Vec<u8>) {
+        let algorithm = der(SEQUENCE, &der(0x06, &[0x2A, 0x86, 0x48, 0xCE, 0x3D, 0x02, 0x01]));
+        let spki = der(SEQUENCE, &[algorithm.clone(), der(0x03, public_key)].concat());
+        let tbs = der(SEQUENCE, &[
+            der(EXPLICIT_VERSION, &der(0x02, &[0x02])),
+            der(0x02, &[0x01]),
+            algorithm.clone(),
+            der(SEQUENCE, b""),
+            der(SEQUENCE, b""),
+            der(SEQUENCE, b""),
+            spki.clone(),
+        ].concat());
+        let cert = der(SEQUENCE, &[tbs, algorithm, der(0x03, &[0_u8; 200])].concat());
+        (cert, spki)
+    }
+
+    fn tlsa_record(usage: u8, selector: u8, matching_type: u8, data: &[u8]) -> DnsRecord {
+        let mut record = DnsRecord::new();
+        record.body = DnsRecordBody::TLSA { usage, selector, matching_type, data: data.to_vec() };
+        record
+    }
+
+    #[test]
+    fn test_subject_public_key_info() {
+        let (cert, spki) = certificate(&[0x04; 65]);
+        assert_eq!(subject_public_key_info(&cert).unwrap(), &spki[..]);
+        assert!(subject_public_key_info(&cert[..40]).is_err());
+        assert!(subject_public_key_info(b"").is_err());
+    }
+
+    #[test]
+    fn test_verify_tlsa() {
+        let (server, server_spki) = certificate(&[0x04; 65]);
+        let (issuer, _) = certificate(&[0x05; 65]);
+        let chain = [&server[..], &issuer[..]];
+
+        let spki_sha256 = digest::digest(&digest::SHA256, &server_spki);
+        let cert_sha512 = digest::digest(&digest::SHA512, &issuer);
+
+        assert!(verify_tlsa(&chain, &[tlsa_record(3, 1, 1, spki_sha256.as_ref())]).unwrap());
+        assert!(verify_tlsa(&chain, &[tlsa_record(3, 0, 0, &server)]).unwrap());
+        assert!(verify_tlsa(&chain, &[tlsa_record(2, 0, 2, cert_sha512.as_ref())]).unwrap());
+
+        // Right data, but the usage points at the wrong certificate
+        assert!(!verify_tlsa(&chain, &[tlsa_record(2, 1, 1, spki_sha256.as_ref())]).unwrap());
+        assert!(!verify_tlsa(&chain, &[tlsa_record(3, 0, 2, cert_sha512.as_ref())]).unwrap());
+
+        // Unknown matching types never match
+        assert!(!verify_tlsa(&chain, &[tlsa_record(3, 1, 3, spki_sha256.as_ref())]).unwrap());
+        assert!(verify_tlsa(&[], &[]).is_err());
+    }
+}
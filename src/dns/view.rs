@@ -0,0 +1,486 @@
+use crate::buffer::{escape_label, read_name, split_name, unescape_label, DnsBuffer};
+use crate::error::{DnsError, Result, Section};
+use crate::name::DnsName;
+use crate::packet::{DnsHeader, DnsQuestion, DnsRecord, Opcode, RecordClass, RecordType, ResponseCode};
+
+const HEADER_SIZE: usize = 12;
+
+/// A read-only view of a DNS packet, borrowing the received bytes rather than
+/// copying them into a `DnsPacket`.  The structure of the packet is checked
+/// once up front, after which questions and records are read lazily and
+/// names are only decompressed when asked for.
+///
+/// Record data is not checked, so a record's RDATA may still fail to parse
+/// when it is converted with `RecordView::to_record` or `Records::to_records`.
+#[derive(Debug, Clone, Copy)]
+pub struct DnsPacketView<'a> {
+    bytes: &'a [u8],
+    // Offsets of the first question and of the first record of each section
+    answers_start: usize,
+    authorities_start: usize,
+    additional_start: usize,
+    // Offset of the OPT pseudo-record, if there is one
+    edns_pos: Option<usize>,
+}
+
+impl<'a> DnsPacketView<'a> {
+    /// Checks that the header counts, names and record lengths of `bytes` all
+    /// fit together, without allocating.
+    pub fn new(bytes: &'a [u8]) -> Result<DnsPacketView<'a>> {
+        if bytes.len() < HEADER_SIZE {
+            return Err(DnsError::Truncated { offset: bytes.len() }.in_section(Section::Header));
+        }
+        let count = |pos: usize| u16::from_be_bytes([bytes[pos], bytes[pos + 1]]);
+
+        let mut pos = HEADER_SIZE;
+        for _ in 0..count(4) {
+            pos = skip_question(bytes, pos).map_err(|err| err.in_section(Section::Question))?;
+        }
+        let answers_start = pos;
+        for _ in 0..count(6) {
+            pos = skip_record(bytes, pos).map_err(|err| err.in_section(Section::Answer))?;
+        }
+        let authorities_start = pos;
+        for _ in 0..count(8) {
+            pos = skip_record(bytes, pos).map_err(|err| err.in_section(Section::Authority))?;
+        }
+        let additional_start = pos;
+        let mut edns_pos = None;
+        for _ in 0..count(10) {
+            let record_pos = pos;
+            pos = skip_record(bytes, pos).map_err(|err| err.in_section(Section::Additional))?;
+            if record_type_at(bytes, record_pos) == RecordType::OPT {
+                if edns_pos.is_some() || bytes[record_pos] != 0 {
+                    let message = String::from("Packet contains a misplaced OPT record");
+                    return Err(DnsError::Malformed { offset: record_pos, message }.in_section(Section::Additional));
+                }
+                edns_pos = Some(record_pos);
+            }
+        }
+
+        Ok(DnsPacketView { bytes, answers_start, authorities_start, additional_start, edns_pos })
+    }
+
+    /// The bytes of the packet being viewed.
+    pub fn as_bytes(&self) -> &'a [u8] {
+        self.bytes
+    }
+
+    pub fn id(&self) -> u16 {
+        u16::from_be_bytes([self.bytes[0], self.bytes[1]])
+    }
+
+    pub fn opcode(&self) -> Opcode {
+        Opcode::from_num((self.bytes[2] & 0x78) >> 3)
+    }
+
+    /// The full response code, including the upper bits from the OPT record.
+    pub fn response_code(&self) -> ResponseCode {
+        let mut code = (self.bytes[3] & 0x0F) as u16;
+        if let Some(pos) = self.edns_pos {
+            // Skip the root name, type and UDP payload size to reach the TTL
+            code |= (self.bytes[pos + 5] as u16) << 4;
+        }
+        ResponseCode::from_num(code)
+    }
+
+    /// A copy of the header, with the full response code.
+    pub fn header(&self) -> DnsHeader {
+        let flags = self.bytes[2];
+        DnsHeader {
+            id: self.id(),
+            query_response: flags & 0x80 == 0x80,
+            opcode: self.opcode(),
+            authoritative_answer: flags & 0x04 == 0x04,
+            truncated_message: flags & 0x02 == 0x02,
+            recursion_desired: flags & 0x01 == 0x01,
+            recursion_available: self.bytes[3] & 0x80 == 0x80,
+            z: (self.bytes[3] & 0x70) >> 4,
+            response_code: self.response_code(),
+            question_count: self.count(4),
+            answer_count: self.count(6),
+            nameserver_count: self.count(8),
+            additional_count: self.count(10),
+        }
+    }
+
+    pub fn questions(&self) -> Questions<'a> {
+        Questions { bytes: self.bytes, pos: HEADER_SIZE, remaining: self.count(4) }
+    }
+
+    pub fn answers(&self) -> Records<'a> {
+        Records { bytes: self.bytes, pos: self.answers_start, remaining: self.count(6), skip: None }
+    }
+
+    pub fn authorities(&self) -> Records<'a> {
+        Records { bytes: self.bytes, pos: self.authorities_start, remaining: self.count(8), skip: None }
+    }
+
+    /// The additional records, leaving out the OPT record just like
+    /// `DnsPacket::additional`.
+    pub fn additional(&self) -> Records<'a> {
+        Records { bytes: self.bytes, pos: self.additional_start, remaining: self.count(10), skip: self.edns_pos }
+    }
+
+    /// The OPT pseudo-record, as a raw record whose class and TTL hold the
+    /// EDNS fields.
+    pub fn edns(&self) -> Option<RecordView<'a>> {
+        self.edns_pos.map(|pos| RecordView::at(self.bytes, pos))
+    }
+
+    fn count(&self, pos: usize) -> u16 {
+        u16::from_be_bytes([self.bytes[pos], self.bytes[pos + 1]])
+    }
+}
+
+/// A compressed name inside a packet, decompressed as its labels are read.
+#[derive(Debug, Clone, Copy)]
+pub struct NameView<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> NameView<'a> {
+    /// The labels of the name from left to right, following compression
+    /// pointers along the way.  The root name has none.
+    pub fn labels(&self) -> Labels<'a> {
+        Labels { bytes: self.bytes, pos: self.pos }
+    }
+
+    /// Copies the name into an owned `DnsName`.
+    pub fn to_name(&self) -> DnsName {
+        // Can't fail, the length of every name was checked in DnsPacketView::new
+        DnsName::from_labels(self.labels()).unwrap_or_default()
+    }
+
+    /// Compares against a name in master file format, ignoring ASCII case and
+    /// any trailing dot.
+    pub fn eq_ignore_case(&self, name: &str) -> bool {
+        let mut parts = split_name(name).into_iter();
+        self.labels().all(|label| parts.next()
+            .and_then(|part| unescape_label(part).ok())
+            .is_some_and(|part| part.eq_ignore_ascii_case(label))) && parts.next().is_none()
+    }
+}
+
+impl std::fmt::Display for NameView<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let mut delimiter = "";
+        for label in self.labels() {
+            let mut escaped = String::new();
+            escape_label(label, &mut escaped);
+            write!(f, "{}{}", delimiter, escaped)?;
+            delimiter = ".";
+        }
+        Ok(())
+    }
+}
+
+pub struct Labels<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Iterator for Labels<'a> {
+    type Item = &'a [u8];
+
+    fn next(&mut self) -> Option<&'a [u8]> {
+        // Every name was checked by read_name in DnsPacketView::new, so
+        // pointers always lead backwards and labels always fit in the packet
+        loop {
+            let len = self.bytes[self.pos] as usize;
+            if len & 0xC0 == 0xC0 {
+                self.pos = (len & 0x3F) << 8 | self.bytes[self.pos + 1] as usize;
+            } else if len == 0 {
+                return None;
+            } else {
+                let label = &self.bytes[self.pos + 1..self.pos + 1 + len];
+                self.pos += 1 + len;
+                return Some(label);
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct QuestionView<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    // Offset of the type, just past the name
+    fields: usize,
+}
+
+impl<'a> QuestionView<'a> {
+    pub fn name(&self) -> NameView<'a> {
+        NameView { bytes: self.bytes, pos: self.pos }
+    }
+
+    pub fn record_type(&self) -> RecordType {
+        RecordType::from_num(read_u16(self.bytes, self.fields))
+    }
+
+    pub fn record_class(&self) -> RecordClass {
+        RecordClass::from_num(read_u16(self.bytes, self.fields + 2))
+    }
+
+    /// Copies the question into an owned `DnsQuestion`.
+    pub fn to_question(&self) -> DnsQuestion {
+        let mut question = DnsQuestion::new();
+        question.name = self.name().to_name();
+        question.record_type = self.record_type();
+        question.record_class = self.record_class();
+        question
+    }
+}
+
+pub struct Questions<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    remaining: u16,
+}
+
+impl<'a> Iterator for Questions<'a> {
+    type Item = QuestionView<'a>;
+
+    fn next(&mut self) -> Option<QuestionView<'a>> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        let fields = name_end(self.bytes, self.pos);
+        let question = QuestionView { bytes: self.bytes, pos: self.pos, fields };
+        self.pos = fields + 4;
+        Some(question)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RecordView<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    // Offset of the type, just past the owner name
+    fields: usize,
+}
+
+impl<'a> RecordView<'a> {
+    fn at(bytes: &'a [u8], pos: usize) -> RecordView<'a> {
+        RecordView { bytes, pos, fields: name_end(bytes, pos) }
+    }
+
+    pub fn name(&self) -> NameView<'a> {
+        NameView { bytes: self.bytes, pos: self.pos }
+    }
+
+    pub fn record_type(&self) -> RecordType {
+        RecordType::from_num(read_u16(self.bytes, self.fields))
+    }
+
+    pub fn record_class(&self) -> RecordClass {
+        RecordClass::from_num(read_u16(self.bytes, self.fields + 2))
+    }
+
+    pub fn ttl(&self) -> u32 {
+        let ttl = &self.bytes[self.fields + 4..self.fields + 8];
+        u32::from_be_bytes([ttl[0], ttl[1], ttl[2], ttl[3]])
+    }
+
+    /// The raw RDATA.  Names inside it may be compressed, pointing elsewhere
+    /// in the packet.
+    pub fn data(&self) -> &'a [u8] {
+        let start = self.fields + 10;
+        &self.bytes[start..start + read_u16(self.bytes, self.fields + 8) as usize]
+    }
+
+    /// Parses the record into an owned `DnsRecord`, checking its RDATA.
+    /// Names in the RDATA may point back anywhere before the record, so
+    /// everything up to its end is copied.  `Records::to_records` converts a
+    /// whole section with a single copy instead.
+    pub fn to_record(&self) -> Result<DnsRecord> {
+        self.read_from(&mut DnsBuffer::from_bytes(&self.bytes[..self.end()]))
+    }
+
+    // `buf` must hold the viewed packet, at least up to the end of the record
+    fn read_from(&self, buf: &mut DnsBuffer) -> Result<DnsRecord> {
+        buf.seek(self.pos)?;
+        let mut record = DnsRecord::new();
+        record.read(buf)?;
+        Ok(record)
+    }
+
+    fn end(&self) -> usize {
+        self.fields + 10 + self.data().len()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Records<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+    remaining: u16,
+    // A record to leave out, namely the OPT record
+    skip: Option<usize>,
+}
+
+impl Records<'_> {
+    /// Parses the remaining records into owned `DnsRecord`s, sharing one copy
+    /// of the packet between them.
+    pub fn to_records(self) -> Result<Vec<DnsRecord>> {
+        let mut buf = DnsBuffer::from_bytes(self.bytes);
+        self.map(|record| record.read_from(&mut buf)).collect()
+    }
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = RecordView<'a>;
+
+    fn next(&mut self) -> Option<RecordView<'a>> {
+        while self.remaining > 0 {
+            self.remaining -= 1;
+            let record = RecordView::at(self.bytes, self.pos);
+            self.pos = record.end();
+            if Some(record.pos) != self.skip {
+                return Some(record);
+            }
+        }
+        None
+    }
+}
+
+fn read_u16(bytes: &[u8], pos: usize) -> u16 {
+    u16::from_be_bytes([bytes[pos], bytes[pos + 1]])
+}
+
+fn record_type_at(bytes: &[u8], pos: usize) -> RecordType {
+    RecordType::from_num(read_u16(bytes, name_end(bytes, pos)))
+}
+
+/// The offset just past a name that has already been checked.
+fn name_end(bytes: &[u8], mut pos: usize) -> usize {
+    loop {
+        let len = bytes[pos] as usize;
+        if len & 0xC0 == 0xC0 {
+            return pos + 2;
+        } else if len == 0 {
+            return pos + 1;
+        }
+        pos += 1 + len;
+    }
+}
+
+fn skip_question(bytes: &[u8], pos: usize) -> Result<usize> {
+    let end = read_name(bytes, pos, |_| ())? + 4;
+    if end > bytes.len() {
+        return Err(DnsError::Truncated { offset: bytes.len() });
+    }
+    Ok(end)
+}
+
+fn skip_record(bytes: &[u8], pos: usize) -> Result<usize> {
+    let fields = read_name(bytes, pos, |_| ())?;
+    if fields + 10 > bytes.len() {
+        return Err(DnsError::Truncated { offset: bytes.len() });
+    }
+    let end = fields + 10 + read_u16(bytes, fields + 8) as usize;
+    if end > bytes.len() {
+        return Err(DnsError::Truncated { offset: bytes.len() });
+    }
+    Ok(end)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::packet::DnsPacket;
+
+    fn parse(bytes: &[u8]) -> DnsPacket {
+        let mut packet = DnsPacket::new();
+        packet.read(&mut DnsBuffer::from_bytes(bytes)).unwrap();
+        packet
+    }
+
+    #[test]
+    fn test_matches_owned_parser() {
+        for file in ["response.txt", "www.yahoo.com.response.txt", "www.example.com.a.response.txt", "missing.example.com.a.response.txt"] {
+            let bytes = fs::read(file).unwrap();
+            let packet = parse(&bytes);
+            let view = DnsPacketView::new(&bytes).unwrap();
+
+            assert_eq!(view.header(), packet.header);
+            assert_eq!(view.questions().map(|question| question.to_question()).collect::<Vec<_>>(), packet.questions);
+            for (records, owned) in [(view.answers(), &packet.answers), (view.authorities(), &packet.authorities),
+                                     (view.additional(), &packet.additional)] {
+                let single: Vec<DnsRecord> = records.clone().map(|record| record.to_record().unwrap()).collect();
+                assert_eq!(&single, owned);
+                assert_eq!(&records.to_records().unwrap(), owned);
+            }
+            assert_eq!(view.edns().is_some(), packet.edns.is_some());
+        }
+    }
+
+    #[test]
+    fn test_lazy_names() {
+        let bytes = fs::read("www.yahoo.com.response.txt").unwrap();
+        let view = DnsPacketView::new(&bytes).unwrap();
+        assert_eq!(view.id(), 49323);
+        assert_eq!(view.response_code(), ResponseCode::NOERROR);
+
+        let question = view.questions().next().unwrap();
+        assert_eq!(question.name().labels().collect::<Vec<_>>(), vec![&b"www"[..], b"yahoo", b"com"]);
+        assert!(question.name().eq_ignore_case("WWW.Yahoo.com."));
+        assert!(!question.name().eq_ignore_case("yahoo.com"));
+        assert!(!question.name().eq_ignore_case("www.yahoo.com.au"));
+        assert_eq!(question.record_type(), RecordType::A);
+
+        let answer = view.answers().next().unwrap();
+        assert_eq!(answer.name().to_string(), "www.yahoo.com");
+        assert_eq!(answer.record_type(), RecordType::CNAME);
+        assert_eq!(answer.ttl(), 857);
+        assert_eq!(answer.data().len(), 22);
+    }
+
+    #[test]
+    fn test_extended_response_code() {
+        let mut packet = DnsPacket::from_query("example.com", "A").unwrap();
+        packet.add_edns(1232);
+        packet.header.response_code = ResponseCode::BADCOOKIE;
+        let mut buf = DnsBuffer::new();
+        packet.write(&mut buf).unwrap();
+
+        let view = DnsPacketView::new(buf.as_bytes()).unwrap();
+        assert_eq!(view.response_code(), ResponseCode::BADCOOKIE);
+        assert_eq!(view.additional().count(), 0);
+        assert_eq!(view.edns().unwrap().record_class(), RecordClass::UNKNOWN(1232));
+    }
+
+    #[test]
+    fn test_malformed() {
+        let header = [0x12, 0x34, 0x01, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00];
+        let packet = |question: &[u8]| [&header[..], question].concat();
+
+        assert!(DnsPacketView::new(&header[..8]).is_err());
+        assert!(DnsPacketView::new(&packet(b"\x03www\x00\x00\x01\x00\x01")).is_ok());
+
+        let err = DnsPacketView::new(&packet(b"\x03www\x00\x00\x01")).unwrap_err();
+        assert_eq!(err.section(), Some(Section::Question));
+
+        // A pointer to itself, and one pointing forwards
+        let err = DnsPacketView::new(&packet(b"\xC0\x0C\x00\x01\x00\x01")).unwrap_err();
+        assert!(matches!(err, DnsError::Section { error, .. } if matches!(*error, DnsError::PointerLoop { offset: 12 })));
+        let err = DnsPacketView::new(&packet(b"\xC0\x0E\x00\x00\x01\x00\x01")).unwrap_err();
+        assert!(matches!(err, DnsError::Section { error, .. } if matches!(*error, DnsError::ForwardPointer { offset: 12 })));
+
+        // A reserved label type, and a label longer than the data left
+        let err = DnsPacketView::new(&packet(b"\x43www\x00\x00\x01\x00\x01")).unwrap_err();
+        assert!(matches!(err, DnsError::Section { error, .. } if matches!(*error, DnsError::ReservedLabelType { .. })));
+        let err = DnsPacketView::new(&packet(b"\x3Fwww\x00\x00\x01\x00\x01")).unwrap_err();
+        assert!(matches!(err, DnsError::Section { error, .. } if matches!(*error, DnsError::Truncated { .. })));
+
+        // A name of five 63 byte labels, over the 255 byte limit
+        let mut name = [&[0x3F][..], &[b'a'; 63][..]].concat().repeat(5);
+        name.extend_from_slice(b"\x00\x00\x01\x00\x01");
+        let err = DnsPacketView::new(&packet(&name)).unwrap_err();
+        assert!(matches!(err, DnsError::Section { error, .. } if matches!(*error, DnsError::NameTooLong { .. })));
+    }
+}
@@ -1,15 +1,13 @@
-use std::fs::File;
+use std::fs;
 use std::env;
-use std::io::Read;
 
 use dns::buffer::DnsBuffer;
 use dns::packet::DnsPacket;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
-    let mut buf = DnsBuffer::new();
-    let mut f = File::open(&args[1]).unwrap();
-    f.read(&mut buf.buf).unwrap();
+    let bytes = fs::read(&args[1]).unwrap();
+    let mut buf = DnsBuffer::from_bytes(&bytes);
 
     let mut packet = DnsPacket::new();
     packet.read(&mut buf).unwrap();